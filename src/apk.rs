@@ -1,593 +1,4876 @@
+mod cargo_config;
+mod emulator;
+mod gradle;
+mod overlay;
+mod placeholders;
+
 use std::{
-    fs,
-    io::{BufRead, BufReader},
-    path::PathBuf,
+    collections::BTreeMap,
+    env, fs,
+    io::{self, BufRead, BufReader},
+    panic,
+    path::{Path, PathBuf},
     process,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
-use cargo_metadata::camino::Utf8Path;
-use clap::{Parser, Subcommand};
+use cargo_metadata::camino::{Utf8Path, Utf8PathBuf};
+use clap::{Parser, Subcommand, ValueEnum};
 use eyre::Context;
+use notify::{RecursiveMode, Watcher};
 use owo_colors::OwoColorize;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::Level;
+use xcommon::ZipFileOptions;
 
-use crate::OriMetadata;
+use crate::{timings, GlobalOptions, OriMetadata};
 
 const CLASSES_DEX: &[u8] = include_bytes!("classes.dex");
 
+/// The activity implemented by the bundled `classes.dex`.
+const ORI_ACTIVITY: &str = "ori.oriactivity.OriActivity";
+
+const DEBUG_APPLICATION_ID_SUFFIX: &str = ".debug";
+
+/// The API level of the SDK platform built against and targeted.
+const SDK_VERSION: u32 = 34;
+
+/// The Android release of [`SDK_VERSION`], the platform build version name.
+const SDK_RELEASE: u32 = 14;
+
+const MIN_SDK_VERSION: u32 = 21;
+
+const DEFAULT_THEME: &str = "@android:style/Theme.DeviceDefault.NoActionBar.TranslucentDecor";
+
+const SCREEN_ORIENTATIONS: &[&str] = &[
+    "unspecified",
+    "behind",
+    "landscape",
+    "portrait",
+    "reverseLandscape",
+    "reversePortrait",
+    "sensorLandscape",
+    "sensorPortrait",
+    "userLandscape",
+    "userPortrait",
+    "sensor",
+    "fullSensor",
+    "nosensor",
+    "user",
+    "fullUser",
+    "locked",
+];
+
+/// The configuration changes the main activity handles itself by default,
+/// instead of being recreated.
+const DEFAULT_CONFIG_CHANGES: &[&str] = &[
+    "orientation",
+    "keyboardHidden",
+    "keyboard",
+    "screenSize",
+    "smallestScreenSize",
+    "locale",
+    "layoutDirection",
+    "fontScale",
+    "screenLayout",
+    "density",
+    "uiMode",
+];
+
+/// The values of `android:configChanges`.
+const CONFIG_CHANGES: &[&str] = &[
+    "colorMode",
+    "density",
+    "fontScale",
+    "fontWeightAdjustment",
+    "grammaticalGender",
+    "keyboard",
+    "keyboardHidden",
+    "layoutDirection",
+    "locale",
+    "mcc",
+    "mnc",
+    "navigation",
+    "orientation",
+    "screenLayout",
+    "screenSize",
+    "smallestScreenSize",
+    "touchscreen",
+    "uiMode",
+];
+
+/// The values of `android:launchMode`.
+const LAUNCH_MODES: &[&str] = &[
+    "standard",
+    "singleTop",
+    "singleTask",
+    "singleInstance",
+    "singleInstancePerTask",
+];
+
+/// The flags of `android:windowSoftInputMode`, at most one `state...` and
+/// one `adjust...` each.
+const SOFT_INPUT_MODES: &[&str] = &[
+    "stateUnspecified",
+    "stateUnchanged",
+    "stateHidden",
+    "stateAlwaysHidden",
+    "stateVisible",
+    "stateAlwaysVisible",
+    "adjustUnspecified",
+    "adjustResize",
+    "adjustPan",
+    "adjustNothing",
+];
+
 #[derive(Subcommand)]
 pub enum Command {
     /// Build an APK from a Cargo project.
     Build(BuildOptions),
 
     /// Install an APK using adb.
-    Install(BuildOptions),
-}
+    Install(InstallOptions),
 
-impl Command {
-    pub fn run(self) -> eyre::Result<()> {
-        match self {
-            Command::Build(options) => {
-                let metadata = crate::get_cargo_metadata()?;
-                let package = get_package(&metadata, options.package.as_deref())?;
-
-                let ori_metadata = OriMetadata::from_package(package)?;
-                let apk_metadata = Metadata::from_package(package)?;
-                let manifest = apk_manifest(package, &ori_metadata, &apk_metadata)?;
-
-                build_apk(
-                    &metadata,
-                    package,
-                    &ori_metadata,
-                    &apk_metadata,
-                    &manifest,
-                    &options,
-                )?;
-            }
-
-            Command::Install(mut options) => {
-                let metadata = crate::get_cargo_metadata()?;
-                let package = get_package(&metadata, options.package.as_deref())?;
-
-                let devices = get_devices()?;
-                let device = if devices.len() == 1 {
-                    &devices[0]
-                } else {
-                    eyre::bail!("No device selected, use `--device`")
-                };
-
-                if options.target.is_none() {
-                    options.target = Some(String::from(device.target_triple()));
-                }
+    /// Install an APK using adb and launch it.
+    Run(RunOptions),
 
-                let ori_metadata = OriMetadata::from_package(package)?;
-                let apk_metadata = Metadata::from_package(package)?;
-                let manifest = apk_manifest(package, &ori_metadata, &apk_metadata)?;
+    /// Remove generated APKs, symbols and stripped libraries and, optionally,
+    /// the downloaded SDK.
+    Clean(CleanOptions),
 
-                install_apk(
-                    &metadata,
-                    package,
-                    &ori_metadata,
-                    &apk_metadata,
-                    &manifest,
-                    device,
-                    &options,
-                )?;
-            }
-        }
+    /// List the devices known to adb, with their ABI and target triple.
+    Devices(DevicesOptions),
 
-        Ok(())
-    }
-}
+    /// Download the android SDK, and the NDK if configured, without building.
+    Sdk(SdkOptions),
 
-#[derive(Parser)]
-pub struct BuildOptions {
-    /// Path to the android SDK root.
-    #[clap(long)]
-    pub sdk: Option<PathBuf>,
+    /// Copy a file off the device, from the app's data directory for
+    /// relative paths.
+    Pull(PullOptions),
 
-    /// Build the artifact in release mode, with optimizations.
-    #[clap(short, long)]
-    pub release: bool,
+    /// Export a Gradle project to open in Android Studio, a one-way snapshot
+    /// of the generated manifest and built libraries.
+    ExportGradle(ExportGradleOptions),
 
-    /// Path to the PEM encoded RSA2048 signing key and certificate.
-    #[clap(long)]
-    pub pem: Option<PathBuf>,
+    /// List the users of the connected device, for `--user`.
+    Users(UsersOptions),
 
-    /// The target platform for the APK.
-    #[clap(long)]
-    pub target: Option<String>,
+    /// Print the versions of cargo-ori and its tools, and the SDK and NDK
+    /// paths, for bug reports.
+    Env(EnvOptions),
 
-    /// Cargo package to build.
-    #[clap(short, long)]
-    pub package: Option<String>,
+    /// Download a single SDK package, run as a subprocess by the SDK and NDK
+    /// downloads.
+    #[clap(hide = true)]
+    DownloadPackage(DownloadPackageOptions),
+}
 
-    /// Run without accessing the network.
-    #[clap(long)]
-    pub offline: bool,
+impl Command {
+    /// The `--verbose` level of the command, `0` for commands without it.
+    pub fn verbosity(&self) -> u8 {
+        match self {
+            Command::Build(options) => options.verbose,
+            Command::Install(options) => options.build.verbose,
+            Command::Run(options) => options.install.build.verbose,
+            Command::Pull(options) => options.build.verbose,
+            Command::ExportGradle(options) => options.build.verbose,
+            _ => 0,
+        }
+    }
 
-    /// Features to enable.
-    #[clap(short = 'F', long)]
-    pub features: Vec<String>,
+    pub fn run(self, global: &GlobalOptions) -> eyre::Result<()> {
+        match self {
+            Command::Build(options) => {
+                build(&options, global)?;
+            }
 
-    /// Use verbose output.
-    #[clap(short, long)]
-    pub verbose: bool,
-}
+            Command::Install(options) if options.watch => {
+                watch(&options.build, || install(&options, global).map(|_| ()))?;
+            }
 
-#[derive(Default, Deserialize)]
-#[serde(deny_unknown_fields)]
-#[serde(rename_all = "kebab-case")]
-struct Metadata {
-    package: Option<String>,
+            Command::Install(options) => {
+                install(&options, global)?;
+            }
 
-    /// The version code of the APK.
-    version_code: Option<u32>,
+            Command::Run(options) if options.install.watch => {
+                watch(&options.install.build, || install_and_run(&options, global))?;
+            }
 
-    /// The version name of the APK.
-    version_name: Option<String>,
+            Command::Run(options) => {
+                install_and_run(&options, global)?;
+            }
 
-    /// The icon of the APK.
-    icon: Option<String>,
+            Command::Clean(options) => {
+                clean(&options, global)?;
+            }
 
-    #[serde(default)]
-    uses_feature: Vec<String>,
+            Command::Devices(options) => {
+                ADB_TIMEOUT.store(options.adb_timeout, Ordering::Relaxed);
+                list_devices()?;
+            }
 
-    #[serde(default)]
-    uses_permission: Vec<String>,
-}
+            Command::Sdk(options) => {
+                install_sdk(&options)?;
+            }
 
-impl Metadata {
-    fn from_package(package: &cargo_metadata::Package) -> eyre::Result<Self> {
-        match package.metadata.get("apk") {
-            Some(value) => Ok(serde_json::from_value(value.clone())?),
-            None => Ok(Self::default()),
+            Command::Pull(options) => {
+                pull(&options, global)?;
+            }
+
+            Command::ExportGradle(options) => {
+                export_gradle(&options, global)?;
+            }
+
+            Command::Users(options) => {
+                ADB_TIMEOUT.store(options.adb_timeout, Ordering::Relaxed);
+                list_users(global)?;
+            }
+
+            Command::Env(options) => {
+                print_env(&options);
+            }
+
+            Command::DownloadPackage(options) => {
+                download_package_in_process(&options);
+            }
         }
+
+        Ok(())
     }
 }
 
-struct Device {
-    id: String,
-    arch: apk::Target,
-}
+fn build(options: &BuildOptions, global: &GlobalOptions) -> eyre::Result<()> {
+    let mut options = options.clone();
 
-impl Device {
-    fn target_triple(&self) -> &'static str {
-        match self.arch {
-            apk::Target::Arm64V8a => "aarch64-linux-android",
-            apk::Target::ArmV7a => "arm7-linux-androideabi",
-            apk::Target::X86 => "i686-linux-android",
-            apk::Target::X86_64 => "x86_64-linux-android",
-        }
+    let project = Project::load(&mut options)?;
+    options.default_targets();
+    build_apk(&project, &options, global)?;
+
+    if options.timings {
+        timings::print();
     }
+
+    Ok(())
 }
 
-fn get_devices() -> eyre::Result<Vec<Device>> {
-    let output = process::Command::new("adb").arg("devices").output()?;
+/// The package being built and everything derived from it, computed once
+/// per build and shared by building and installing.
+struct Project {
+    metadata: cargo_metadata::Metadata,
+    package: cargo_metadata::Package,
+    ori_metadata: OriMetadata,
+    apk_metadata: Metadata,
+    manifest: apk::AndroidManifest,
+}
 
-    let mut devices = Vec::new();
+impl Project {
+    /// Load the selected package, adding the features of `--flavor` to
+    /// `options`.
+    fn load(options: &mut BuildOptions) -> eyre::Result<Self> {
+        let start = Instant::now();
+        let metadata = crate::get_cargo_metadata(
+            options.manifest_path.as_deref(),
+            options.target_dir.as_deref(),
+            options.cargo_flags(),
+        )?;
+        timings::record("metadata", start);
 
-    let mut lines = output.stdout.lines();
-    lines.next();
+        let package = get_package(&metadata, options.package.as_deref())?.clone();
+        tracing::debug!(
+            package = %package.name,
+            target_directory = %metadata.target_directory,
+            "resolved package"
+        );
 
-    for line in lines {
-        let line = line?;
+        let ori_metadata = OriMetadata::from_package(&package)?;
+        let mut apk_metadata = Metadata::from_package(&package)?;
 
-        if line.trim().is_empty() {
-            continue;
+        if let Some(ref flavor) = options.flavor {
+            let features = apk_metadata.apply_flavor(flavor)?;
+            options.features.extend(features);
         }
 
-        let id = line
-            .split_whitespace()
-            .next()
-            .ok_or_else(|| eyre::eyre!("Malformed adb output"))?;
+        options.target_rustflags = apk_metadata.rustflags.clone();
 
-        let output = process::Command::new("adb")
-            .arg("-s")
-            .arg(id)
-            .arg("shell")
-            .arg("getprop")
-            .arg("ro.product.cpu.abi")
-            .output()?;
+        let lib_name = options.lib_name(&package)?;
+        let debuggable = options.is_debug();
+        let manifest = apk_manifest(&ManifestConfig::new(
+            &package,
+            &lib_name,
+            debuggable,
+            &ori_metadata,
+            &apk_metadata,
+        ))?;
 
-        let arch = String::from_utf8(output.stdout)?;
+        Ok(Self {
+            metadata,
+            package,
+            ori_metadata,
+            apk_metadata,
+            manifest,
+        })
+    }
 
-        let arch = match arch.trim() {
-            "arm64-v8a" => apk::Target::Arm64V8a,
-            "armabi-v7a" => apk::Target::ArmV7a,
-            "x86_64" => apk::Target::X86_64,
-            "x86" => apk::Target::X86,
-            _ => eyre::bail!("Unknown abi `{}`", arch.trim()),
-        };
+    /// The signing key of `--pem`, or else of `signing.pem` for release
+    /// builds, debug builds use the debug key.
+    fn pem_path(&self, options: &BuildOptions) -> eyre::Result<Option<PathBuf>> {
+        if let Some(ref pem) = options.pem {
+            return Ok(Some(pem.clone()));
+        }
 
-        devices.push(Device {
-            id: String::from(id),
-            arch,
-        });
+        match self.apk_metadata.signing {
+            Some(ref signing) if !options.is_debug() => {
+                signing.pem_path(self.package_root()).map(Some)
+            }
+            _ => Ok(None),
+        }
     }
 
-    Ok(devices)
-}
-
-fn get_package<'a>(
-    metadata: &'a cargo_metadata::Metadata,
-    name: Option<&str>,
-) -> eyre::Result<&'a cargo_metadata::Package> {
-    match name {
-        Some(ref package) => metadata
-            .packages
-            .iter()
-            .find(|p| p.name == *package)
-            .ok_or_else(|| eyre::eyre!("Package `{}` not found", package)),
-        None => metadata
-            .root_package()
-            .ok_or_else(|| eyre::eyre!("No package")),
+    fn package_root(&self) -> &Utf8Path {
+        self.package
+            .manifest_path
+            .parent()
+            .expect("manifest_path has parent")
     }
-}
 
-fn install_apk(
-    metadata: &cargo_metadata::Metadata,
-    package: &cargo_metadata::Package,
-    ori_metadata: &OriMetadata,
-    apk_metadata: &Metadata,
-    manifest: &apk::AndroidManifest,
-    device: &Device,
-    options: &BuildOptions,
-) -> eyre::Result<()> {
-    ensure_adb_installed()?;
+    /// The `classes.dex` of `--dex`, or else of `classes-dex`, `None` for
+    /// the bundled one.
+    fn classes_dex_path(&self, options: &BuildOptions) -> eyre::Result<Option<PathBuf>> {
+        let path = match (&options.dex, &self.apk_metadata.classes_dex) {
+            (Some(dex), _) => dex.clone(),
+            (None, Some(dex)) => self.package_root().join(dex).into(),
+            (None, None) => return Ok(None),
+        };
 
-    let apk_path = build_apk(
-        metadata,
-        package,
-        ori_metadata,
-        apk_metadata,
-        manifest,
-        options,
-    )?;
+        check_dex(&path)?;
 
-    println!("  {} installing APK", "Install".green().bold());
+        Ok(Some(path))
+    }
 
-    let output = process::Command::new("adb")
-        .arg("-s")
-        .arg(&device.id)
-        .arg("install")
-        .arg(apk_path)
-        .output()?;
+    /// The extra dex files of the metadata, checked to be dex files.
+    fn extra_dex_paths(&self) -> eyre::Result<Vec<Utf8PathBuf>> {
+        let mut paths = Vec::new();
 
-    if !output.status.success() {
-        eyre::bail!("Install failed");
-    }
+        for dex in self.apk_metadata.dex.iter() {
+            let path = self.package_root().join(dex);
+            check_dex(path.as_ref())?;
+            paths.push(path);
+        }
 
-    println!("    {} APK installed", "Finished".green().bold());
+        Ok(paths)
+    }
 
-    Ok(())
-}
+    /// The icon of `[package.metadata.apk]`, or else of
+    /// `[package.metadata.ori]`, relative to the package root.
+    fn icon_path(&self) -> eyre::Result<Option<Utf8PathBuf>> {
+        let icon = match (&self.apk_metadata.icon, &self.ori_metadata.icon) {
+            (Some(icon), _) | (None, Some(icon)) => icon,
+            (None, None) => return Ok(None),
+        };
 
-fn build_apk(
-    metadata: &cargo_metadata::Metadata,
-    package: &cargo_metadata::Package,
-    ori_metadata: &OriMetadata,
-    apk_metadata: &Metadata,
-    manifest: &apk::AndroidManifest,
-    options: &BuildOptions,
-) -> eyre::Result<PathBuf> {
-    crate::ensure_cross_installed()?;
+        let path = self.package_root().join(icon);
 
-    let target = options
-        .target
-        .as_deref()
-        .ok_or_else(|| eyre::eyre!("Target not specified, use `--target` to do so"))?;
+        if !path.is_file() {
+            eyre::bail!("Icon `{}` does not exist", path);
+        }
 
-    let apk_target = match target {
-        "aarch64-linux-android" => apk::Target::Arm64V8a,
-        "arm7-linux-androidabi" => apk::Target::ArmV7a,
-        "x86_64-linux-android" => apk::Target::X86_64,
-        "i686-linux-android" => apk::Target::X86,
-        _ => eyre::bail!("Target '{}' is not supported for android", target),
-    };
+        Ok(Some(path))
+    }
+}
 
-    let package_root = package
-        .manifest_path
-        .parent()
-        .expect("manifest_path has parent");
+fn check_dex(path: &Path) -> eyre::Result<()> {
+    let mut magic = [0; 8];
+    let mut file = fs::File::open(path)
+        .wrap_err_with(|| format!("Failed to open dex `{}`", path.display()))?;
 
-    let icon_path = apk_metadata
-        .icon
-        .as_ref()
-        .map(|icon| package_root.join(icon))
-        .or_else(|| {
-            ori_metadata
-                .icon
-                .as_ref()
-                .map(|icon| package_root.join(icon))
-        });
+    // `dex\n` followed by a three digit version and a nul
+    let valid = io::Read::read_exact(&mut file, &mut magic).is_ok()
+        && magic.starts_with(b"dex\n")
+        && magic[4..7].iter().all(u8::is_ascii_digit)
+        && magic[7] == 0;
 
-    let artifact = build_lib(
-        package,
-        target,
-        &options.features,
-        options.release,
-        options.offline,
-    )?;
-    let sdk_path = download_android_sdk(&metadata.target_directory, 34)?;
+    if !valid {
+        eyre::bail!("`{}` is not a dex file", path.display());
+    }
 
-    let lib_path = artifact_cdylib(&artifact)?.strip_prefix("/")?;
-    let lib_path = metadata.workspace_root.join(lib_path);
+    Ok(())
+}
 
-    let lib_parent = lib_path.parent().expect("lib_path has parent");
+/// The devices an APK was installed on, and its manifest.
+struct Installed {
+    devices: Vec<Device>,
+    manifest: apk::AndroidManifest,
 
-    let apk_path: PathBuf = lib_parent.join(format!("{}.apk", package.name)).into();
+    /// Kept alive until installing or launching is done, `--kill-emulator`
+    /// shuts it down when dropped.
+    #[allow(dead_code)]
+    emulator: Option<emulator::Emulator>,
+}
 
-    let dex_path = sdk_path
-        .parent()
-        .expect("sdk_path has parent")
-        .join("classes.dex");
+fn install(options: &InstallOptions, global: &GlobalOptions) -> eyre::Result<Installed> {
+    ADB_TIMEOUT.store(options.adb_timeout, Ordering::Relaxed);
+    INSTALL_TIMEOUT.store(options.install_timeout, Ordering::Relaxed);
 
-    fs::write(&dex_path, CLASSES_DEX).wrap_err("Failed to write classes.dex")?;
+    let mut options = options.clone();
 
-    println!(
-        "  {} building APK `{}`",
-        "Build".green().bold(),
-        apk_path.display()
-    );
+    let project = Project::load(&mut options.build)?;
 
-    let mut apk = apk::Apk::new(apk_path.clone(), manifest.clone(), true)
-        .map_err(|e| eyre::eyre!("{}", e))?;
+    let mut emulator = None;
 
-    if let Some(ref icon_path) = icon_path {
-        println!(
-            "   {} adding icon `{}`",
-            "Building".green().bold(),
-            icon_path
-        );
+    if options.emulator.is_some() && global.device.is_some() {
+        eyre::bail!("`--device` can't be used with `--emulator`");
     }
 
-    apk.add_res(icon_path.as_ref().map(AsRef::as_ref), sdk_path.as_ref())
-        .map_err(|e| eyre::eyre!("{}", e))?;
+    let devices = match options.emulator {
+        Some(ref avd) if global.dry_run => {
+            status!(
+                "  {} boot emulator `{}` and install on it",
+                "Plan".green().bold(),
+                avd
+            );
 
-    apk.add_dex(dex_path.as_ref())
-        .map_err(|e| eyre::eyre!("{}", e))?;
+            options.build.default_targets();
 
-    println!(
-        "   {} adding library `{}`",
-        "Building".green().bold(),
-        lib_path
-    );
+            if options.build.target.is_empty() {
+                eyre::bail!(
+                    "Target not specified, use `--target` with `--emulator` and `--dry-run`"
+                );
+            }
 
-    apk.add_lib(apk_target, lib_path.as_ref())
-        .map_err(|e| eyre::eyre!("{}", e))?;
+            Vec::new()
+        }
+        Some(ref avd) => {
+            ensure_adb_installed()?;
 
-    let pem = match options.pem {
-        Some(ref pem) => {
-            println!(
-                "   {} signing APK with pem `{}`",
-                "Building".green().bold(),
-                pem.display()
-            );
+            let booted = emulator::boot(avd, options.kill_emulator)?;
+            let serial = booted.serial.clone();
+            emulator = Some(booted);
 
-            fs::read_to_string(pem).wrap_err("Failed to load PEM file")?
+            get_devices()?
+                .into_iter()
+                .filter(|device| device.id == serial)
+                .collect()
         }
+        None if global.dry_run => selected_devices(global)?,
         None => {
-            println!(
-                "   {} signing APK with debug pem",
-                "Building".green().bold()
-            );
+            ensure_adb_installed()?;
 
-            String::from(include_str!("debug.pem"))
-        }
-    };
+            let timeout = Duration::from_secs(options.adb_timeout);
+            let mut devices = selected_devices(global)?;
 
-    let signer = apk::Signer::new(&pem).map_err(|e| eyre::eyre!("{}", e))?;
+            if devices.is_empty() {
+                status!("  {} for a device", "Waiting".green().bold());
+                wait_for_device(global.device.as_deref(), timeout)?;
+                devices = selected_devices(global)?;
+            }
 
-    apk.finish(Some(signer)).map_err(|e| eyre::eyre!("{}", e))?;
+            for device in devices.iter() {
+                wait_for_device(Some(&device.id), timeout)?;
+            }
 
-    println!("    {} APK built", "Finished".green().bold());
+            devices
+        }
+    };
 
-    Ok(apk_path)
-}
+    if !devices.is_empty() || !global.dry_run || global.device.is_some() {
+        check_device_selection(&devices, Some(options.all_devices), global)?;
+    }
 
-fn build_lib(
-    package: &cargo_metadata::Package,
-    target: &str,
-    features: &[String],
-    release: bool,
-    offline: bool,
-) -> eyre::Result<cargo_metadata::Artifact> {
-    println!(
-        "  {} building library for target `{}`",
-        "Build".green().bold(),
-        target
-    );
+    if options.build.target.is_empty() {
+        for device in devices.iter() {
+            let target = String::from(device.target_triple());
 
-    let mut command = process::Command::new("cross");
+            if !options.build.target.contains(&target) {
+                options.build.target.push(target);
+            }
+        }
+    }
 
-    command
-        .arg("--color")
-        .arg("always")
-        .arg("build")
-        .arg("--target")
-        .arg(target)
-        .arg("--message-format=json")
-        .arg("--package")
-        .arg(&package.name)
-        .arg("--lib");
+    options.build.default_targets();
 
-    if release {
-        command.arg("--release");
-    }
+    install_apk(&project, &devices, &options, global)?;
 
-    if offline {
-        command.arg("--offline");
+    if options.build.timings {
+        timings::print();
     }
 
-    if !features.is_empty() {
-        command.arg("--features");
-        command.arg(features.join(","));
-    }
+    Ok(Installed {
+        devices,
+        manifest: project.manifest,
+        emulator,
+    })
+}
 
-    let process = command
-        .stdout(process::Stdio::piped())
-        .spawn()
-        .wrap_err("Failed to run cross")?;
+/// The connected devices, only the one of `--device` if set.
+fn selected_devices(global: &GlobalOptions) -> eyre::Result<Vec<Device>> {
+    let mut devices = get_devices()?;
 
-    let reader = BufReader::new(process.stdout.expect("stdout available"));
+    if let Some(ref serial) = global.device {
+        devices.retain(|device| device.id == *serial);
+    }
 
-    let mut package_artifact = None;
+    Ok(devices)
+}
 
-    for message in cargo_metadata::Message::parse_stream(reader) {
-        match message? {
-            cargo_metadata::Message::CompilerArtifact(artifact) => {
-                if artifact.package_id == package.id {
-                    package_artifact = Some(artifact);
-                }
+/// Check that some devices are connected, and only one unless `all` is set.
+/// `all` is `None` for commands working on a single device.
+fn check_device_selection(
+    devices: &[Device],
+    all: Option<bool>,
+    global: &GlobalOptions,
+) -> eyre::Result<()> {
+    if devices.is_empty() {
+        match global.device {
+            Some(ref serial) => eyre::bail!("Device `{}` is not connected", serial),
+            None => eyre::bail!("No devices connected"),
+        }
+    }
+
+    if devices.len() > 1 && all != Some(true) {
+        let names: Vec<_> = devices.iter().map(Device::display_name).collect();
+
+        let options = if all.is_some() {
+            "`--device` or `--all-devices`"
+        } else {
+            "`--device`"
+        };
+
+        eyre::bail!(
+            "No device selected, use {}, connected are {}",
+            options,
+            names.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+fn pull(options: &PullOptions, global: &GlobalOptions) -> eyre::Result<()> {
+    ADB_TIMEOUT.store(options.adb_timeout, Ordering::Relaxed);
+
+    let mut build = options.build.clone();
+    let project = Project::load(&mut build)?;
+
+    let package = project
+        .manifest
+        .package
+        .as_deref()
+        .ok_or_else(|| eyre::eyre!("Manifest has no package"))?;
+
+    ensure_adb_installed()?;
+
+    let devices = selected_devices(global)?;
+    check_device_selection(&devices, None, global)?;
+    let device = &devices[0];
+
+    // files outside the app's data directory are readable by adb itself
+    let readable = options.remote.starts_with('/')
+        && !options.remote.starts_with("/data/data/")
+        && !options.remote.starts_with("/data/user/");
+
+    let mut command = process::Command::new("adb");
+    command.arg("-s").arg(&device.id);
+
+    if readable {
+        command.arg("pull").arg(&options.remote).arg(&options.local);
+
+        let output = adb_output(&mut command, Level::DEBUG)?;
+
+        if !output.status.success() {
+            eyre::bail!(
+                "Failed to pull `{}`: {}",
+                options.remote,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+    } else {
+        if !build.is_debug() {
+            eyre::bail!(
+                "Pulling from the app's data directory uses `run-as`, which requires a debug build"
+            );
+        }
+
+        command
+            .arg("exec-out")
+            .arg("run-as")
+            .arg(package)
+            .arg("cat")
+            .arg(shell_quote(&options.remote));
+
+        let output = adb_output(&mut command, Level::DEBUG)?;
+
+        // `exec-out` has no separate stderr, errors of `run-as` and `cat`
+        // end up in stdout
+        let failed = output.stdout.starts_with(b"run-as:") || output.stdout.starts_with(b"cat:");
+
+        if !output.status.success() || failed {
+            eyre::bail!(
+                "Failed to pull `{}` from `{}`: {}",
+                options.remote,
+                package,
+                String::from_utf8_lossy(&output.stdout).trim()
+            );
+        }
+
+        fs::write(&options.local, output.stdout)
+            .wrap_err_with(|| format!("Failed to write `{}`", options.local.display()))?;
+    }
+
+    status!(
+        "      {} `{}` to `{}`",
+        "Pulled".green().bold(),
+        options.remote,
+        options.local.display()
+    );
+
+    Ok(())
+}
+
+fn install_and_run(options: &RunOptions, global: &GlobalOptions) -> eyre::Result<()> {
+    let installed = install(&options.install, global)?;
+
+    for device in installed.devices.iter() {
+        let screenshot = options.screenshot.as_ref().map(|path| {
+            if installed.devices.len() > 1 {
+                screenshot_path(path, &device.id)
+            } else {
+                path.clone()
             }
-            cargo_metadata::Message::CompilerMessage(message) => {
-                println!("{}", message.message);
+        });
+
+        if global.dry_run {
+            status!("  {} launch on `{}`", "Plan".green().bold(), device.id);
+
+            if let Some(ref path) = screenshot {
+                status!(
+                    "  {} screenshot to `{}`",
+                    "Plan".green().bold(),
+                    path.display()
+                );
             }
-            cargo_metadata::Message::BuildScriptExecuted(_) => {}
-            cargo_metadata::Message::BuildFinished(_) => {}
-            cargo_metadata::Message::TextLine(line) => {
-                println!("{}", line);
+
+            continue;
+        }
+
+        launch_app(device, &installed.manifest, options)?;
+
+        if let Some(ref path) = screenshot {
+            thread::sleep(Duration::from_secs(options.screenshot_delay));
+            take_screenshot(device, path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// `path` with `-<id>` appended to its file stem.
+fn screenshot_path(path: &Path, id: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let id = id.replace([':', '/', '\\'], "_");
+
+    let file_name = match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, id, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, id),
+    };
+
+    path.with_file_name(file_name)
+}
+
+fn take_screenshot(device: &Device, path: &Path) -> eyre::Result<()> {
+    let mut command = process::Command::new("adb");
+
+    command
+        .arg("-s")
+        .arg(&device.id)
+        .arg("exec-out")
+        .arg("screencap")
+        .arg("-p");
+
+    let output = adb_output(&mut command, Level::DEBUG)?;
+
+    if !output.status.success() || output.stdout.is_empty() {
+        eyre::bail!(
+            "Failed to take screenshot on `{}`: {}",
+            device.id,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    fs::write(path, output.stdout)
+        .wrap_err_with(|| format!("Failed to write screenshot `{}`", path.display()))?;
+
+    status!(
+        "  {} screenshot `{}`",
+        "Saved".green().bold(),
+        path.display()
+    );
+
+    Ok(())
+}
+
+/// Log `command` with its environment at `level`, `-v` enables debug and
+/// `-vv` trace events.
+fn echo_command(command: &process::Command, level: Level) {
+    let line = display_command(command);
+
+    if level == Level::TRACE {
+        tracing::trace!("running `{}`", line);
+    } else {
+        tracing::debug!("running `{}`", line);
+    }
+}
+
+/// `command` with its environment, as it would be typed into a shell.
+fn display_command(command: &process::Command) -> String {
+    let mut line = String::new();
+
+    for (key, value) in command.get_envs() {
+        let Some(value) = value else {
+            continue;
+        };
+
+        // shown as the equivalent `RUSTFLAGS`, the separators aren't printable
+        if key == "CARGO_ENCODED_RUSTFLAGS" {
+            let flags = value.to_string_lossy().replace('\x1f', " ");
+            line += &format!("{}={} ", key.to_string_lossy(), quote(flags.as_ref()));
+        } else {
+            line += &format!("{}={} ", key.to_string_lossy(), quote(value));
+        }
+    }
+
+    line += &quote(command.get_program());
+
+    for arg in command.get_args() {
+        line += " ";
+        line += &quote(arg);
+    }
+
+    line
+}
+
+/// Quote `arg` if it contains whitespace, so echoed commands can be pasted
+/// into a shell, e.g. with SDK paths under `Program Files`.
+fn quote(arg: &std::ffi::OsStr) -> String {
+    let arg = arg.to_string_lossy();
+
+    if arg.contains(char::is_whitespace) {
+        format!("\"{}\"", arg)
+    } else {
+        arg.into_owned()
+    }
+}
+
+/// How long adb commands may take before they are killed, in seconds.
+static ADB_TIMEOUT: AtomicU64 = AtomicU64::new(30);
+
+/// How long `adb install` may take before it is killed, in seconds.
+static INSTALL_TIMEOUT: AtomicU64 = AtomicU64::new(600);
+
+/// How often a running adb command is checked for completion.
+const ADB_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How often a booting device is polled.
+const BOOT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Run the adb `command` to completion, logging it at `level`, and kill it
+/// when it takes longer than `--adb-timeout`, e.g. on a wedged device.
+fn adb_output(command: &mut process::Command, level: Level) -> eyre::Result<process::Output> {
+    let timeout = Duration::from_secs(ADB_TIMEOUT.load(Ordering::Relaxed));
+    adb_output_within(command, level, timeout, "--adb-timeout")
+}
+
+/// Run the adb `command` like `adb_output`, killing it after `timeout`, which
+/// is set with `flag`.
+fn adb_output_within(
+    command: &mut process::Command,
+    level: Level,
+    timeout: Duration,
+    flag: &str,
+) -> eyre::Result<process::Output> {
+    echo_command(command, level);
+
+    let mut child = command
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::piped())
+        .spawn()?;
+
+    let stdout = read_to_end(child.stdout.take().expect("stdout available"));
+    let stderr = read_to_end(child.stderr.take().expect("stderr available"));
+
+    let start = Instant::now();
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+
+        if start.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+
+            eyre::bail!(
+                "`{}` timed out after {}s, use `{}` to wait longer",
+                display_command(command),
+                timeout.as_secs(),
+                flag
+            );
+        }
+
+        thread::sleep(ADB_POLL_INTERVAL);
+    };
+
+    Ok(process::Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+fn read_to_end(mut reader: impl io::Read + Send + 'static) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        let _ = reader.read_to_end(&mut buffer);
+        buffer
+    })
+}
+
+/// Run `command` to completion, logging it at `level`.
+fn command_output(command: &mut process::Command, level: Level) -> io::Result<process::Output> {
+    echo_command(command, level);
+    command.output()
+}
+
+/// The target triples APKs can be built for.
+const ANDROID_TARGETS: &[&str] = &[
+    "aarch64-linux-android",
+    "armv7-linux-androideabi",
+    "x86_64-linux-android",
+    "i686-linux-android",
+];
+
+fn clean(options: &CleanOptions, global: &GlobalOptions) -> eyre::Result<()> {
+    let metadata = crate::get_cargo_metadata(
+        options.manifest_path.as_deref(),
+        options.target_dir.as_deref(),
+        Vec::new(),
+    )?;
+    let package = get_package(&metadata, options.package.as_deref())?;
+
+    let apk_dir = metadata.target_directory.join("apk");
+    let apk_name = format!("{}.apk", package.name);
+
+    let mut paths = Vec::new();
+
+    // single target APKs and stripped libraries are written next to the
+    // library, for every profile and for examples
+    for target in ANDROID_TARGETS {
+        let profiles = match fs::read_dir(metadata.target_directory.join(target)) {
+            Ok(profiles) => profiles,
+            Err(_) => continue,
+        };
+
+        for profile in profiles {
+            let profile = profile?.path();
+
+            for lib_dir in [profile.clone(), profile.join("examples")] {
+                paths.push(lib_dir.join(&apk_name));
+                paths.push(lib_dir.join("stripped"));
             }
-            _ => {}
         }
     }
 
-    package_artifact.ok_or_else(|| eyre::eyre!("Artifact not generated"))
-}
+    if options.sdk {
+        paths.push(apk_dir.into());
+    } else {
+        paths.push(apk_dir.join(&apk_name).into());
+        paths.push(apk_dir.join(format!("{}.build.json", package.name)).into());
+        paths.push(symbols_path(&metadata.target_directory, &package.name).into());
+        paths.push(apk_dir.join("jobs").into());
+
+        for target in ANDROID_TARGETS {
+            let abi = android_target(target)?.android_abi();
+            paths.push(apk_dir.join(format!("{}-{}.apk", package.name, abi)).into());
+        }
+
+        if let Ok(platforms) = fs::read_dir(apk_dir.join("platforms")) {
+            for platform in platforms {
+                paths.push(platform?.path().join("classes.dex"));
+            }
+        }
+    }
+
+    paths.retain(|path| path.exists());
+
+    if paths.is_empty() {
+        status!("  {} nothing to clean", "Clean".green().bold());
+        return Ok(());
+    }
+
+    for path in paths {
+        if global.dry_run {
+            status!("  {} `{}`", "Would remove".green().bold(), path.display());
+            continue;
+        }
+
+        if path.is_dir() {
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        }
+        .wrap_err_with(|| format!("Failed to remove `{}`", path.display()))?;
+
+        status!("  {} `{}`", "Removed".green().bold(), path.display());
+    }
+
+    Ok(())
+}
+
+fn install_sdk(options: &SdkOptions) -> eyre::Result<()> {
+    let metadata = crate::get_cargo_metadata(
+        options.manifest_path.as_deref(),
+        options.target_dir.as_deref(),
+        Vec::new(),
+    )?;
+
+    let sdk_path = download_android_sdk(&metadata.target_directory, options.sdk_version, false)?;
+    status!("  {} `{}`", "Installed".green().bold(), sdk_path.display());
+
+    let ndk_version = match options.ndk_version {
+        Some(ref version) => Some(version.clone()),
+        None => {
+            let package = get_package(&metadata, options.package.as_deref())?;
+            Metadata::from_package(package)?.ndk_version
+        }
+    };
+
+    if let Some(ref version) = ndk_version {
+        let ndk_path = download_android_ndk(&metadata.target_directory, version, false)?;
+        status!("  {} `{}`", "Installed".green().bold(), ndk_path.display());
+    }
+
+    Ok(())
+}
+
+/// Print the environment as `key: value` lines, reporting what can't be
+/// determined instead of failing, so the output can always be pasted.
+fn print_env(options: &EnvOptions) {
+    let mut lines = Vec::new();
+
+    lines.push(("cargo-ori", String::from(env!("CARGO_PKG_VERSION"))));
+
+    let mut host = format!("{} {}", env::consts::OS, env::consts::ARCH);
+
+    if android_host_os().is_err() {
+        host += " (SDK downloads not supported)";
+    }
+
+    lines.push(("host", host));
+    lines.push(("cargo", tool_version("cargo", "--version")));
+
+    let cross = if crate::is_cross_installed() {
+        tool_version("cross", "--version")
+    } else {
+        String::from("not installed")
+    };
+
+    let adb = if is_adb_installed() {
+        tool_version("adb", "version")
+    } else {
+        String::from("not installed")
+    };
+
+    lines.push(("cross", cross));
+    lines.push(("adb", adb));
+
+    for var in ["ANDROID_HOME", "ANDROID_SDK_ROOT", "ANDROID_NDK_HOME"] {
+        let value = env::var(var).unwrap_or_else(|_| String::from("unset"));
+        lines.push((var, value));
+    }
+
+    let metadata = crate::get_cargo_metadata(
+        options.manifest_path.as_deref(),
+        options.target_dir.as_deref(),
+        Vec::new(),
+    );
+
+    match metadata {
+        Ok(metadata) => {
+            let target_directory = &metadata.target_directory;
+
+            let sdk_path = android_sdk_path(target_directory, SDK_VERSION);
+            let sdk = format!(
+                "android-{} `{}`{}",
+                SDK_VERSION,
+                sdk_path,
+                downloaded(&sdk_path)
+            );
+
+            let package = get_package(&metadata, options.package.as_deref());
+            let ndk_version = package
+                .as_ref()
+                .ok()
+                .and_then(|package| Metadata::from_package(package).ok())
+                .and_then(|metadata| metadata.ndk_version);
+
+            let ndk = match ndk_version {
+                Some(version) => {
+                    let ndk_path = android_ndk_path(target_directory, &version);
+                    format!("{} `{}`{}", version, ndk_path, downloaded(&ndk_path))
+                }
+                None => String::from("not configured"),
+            };
+
+            let package = match package {
+                Ok(package) => format!("{} {}", package.name, package.version),
+                Err(err) => format!("unknown ({})", err),
+            };
+
+            lines.push(("package", package));
+            lines.push(("sdk", sdk));
+            lines.push(("ndk", ndk));
+        }
+        Err(err) => lines.push(("package", format!("unknown ({})", err))),
+    }
+
+    let width = lines.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+
+    // like all human readable output on stderr with `--message-format json`
+    for (key, value) in lines {
+        status!(
+            "{:width$}  {}",
+            format!("{}:", key),
+            value,
+            width = width + 1
+        );
+    }
+}
+
+/// The first line of `program arg`, or why it couldn't be run.
+fn tool_version(program: &str, arg: &str) -> String {
+    match process::Command::new(program).arg(arg).output() {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .next()
+            .map_or_else(|| String::from("unknown"), |line| String::from(line.trim())),
+        Ok(output) => format!("failed with {}", output.status),
+        Err(err) => format!("failed to run ({})", err),
+    }
+}
+
+fn downloaded(path: &Utf8Path) -> &'static str {
+    if path.exists() {
+        ""
+    } else {
+        " (not downloaded)"
+    }
+}
+
+/// How long to wait for more changes before rebuilding.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Run `f`, and again every time the package sources change.
+fn watch(options: &BuildOptions, mut f: impl FnMut() -> eyre::Result<()>) -> eyre::Result<()> {
+    let metadata = crate::get_cargo_metadata(
+        options.manifest_path.as_deref(),
+        options.target_dir.as_deref(),
+        options.cargo_flags(),
+    )?;
+    let package = get_package(&metadata, options.package.as_deref())?;
+
+    let package_root = package
+        .manifest_path
+        .parent()
+        .expect("manifest_path has parent");
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+
+    watcher.watch(package_root.join("src").as_ref(), RecursiveMode::Recursive)?;
+    watcher.watch(package.manifest_path.as_ref(), RecursiveMode::NonRecursive)?;
+
+    loop {
+        if let Err(err) = f() {
+            status!("  {} {:?}", "Error".red().bold(), err);
+        }
+
+        status!("  {} waiting for changes", "Watching".green().bold());
+
+        wait_for_change(&receiver)?;
+
+        status!("  {} rebuilding...", "Changed".green().bold());
+    }
+}
+
+fn wait_for_change(receiver: &mpsc::Receiver<notify::Result<notify::Event>>) -> eyre::Result<()> {
+    loop {
+        let event = receiver.recv()?;
+
+        if !event?.kind.is_access() {
+            break;
+        }
+    }
+
+    // wait until the changes settle, e.g. when an editor writes several files
+    loop {
+        match receiver.recv_timeout(WATCH_DEBOUNCE) {
+            Ok(_) => {}
+            Err(mpsc::RecvTimeoutError::Timeout) => return Ok(()),
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+#[derive(Clone, Parser)]
+pub struct BuildOptions {
+    /// Path to the android SDK root.
+    #[clap(long)]
+    pub sdk: Option<PathBuf>,
+
+    /// Build the artifact in release mode, with optimizations.
+    #[clap(short, long)]
+    pub release: bool,
+
+    /// A debuggable build with the debug suffix and key, granting
+    /// permissions on install.
+    #[clap(long, conflicts_with_all = ["release", "profile", "pem"])]
+    pub dev: bool,
+
+    /// Build the artifact with the specified profile.
+    #[clap(long, conflicts_with = "release")]
+    pub profile: Option<String>,
+
+    /// The expected SHA-256 of the SDK `android.jar`, the build fails if it
+    /// doesn't match.
+    #[clap(long)]
+    pub sdk_sha256: Option<String>,
+
+    /// Path to the PEM encoded RSA2048 signing key and certificate.
+    #[clap(long)]
+    pub pem: Option<PathBuf>,
+
+    /// Write a `sha256sum` compatible checksum of the APK to this path.
+    #[clap(long)]
+    pub checksum_file: Option<PathBuf>,
+
+    /// Use this `classes.dex` instead of the bundled one, e.g. for a
+    /// customized `OriActivity`.
+    #[clap(long)]
+    pub dex: Option<PathBuf>,
+
+    /// The target platforms for the APK, may be repeated to build a fat APK.
+    #[clap(long)]
+    pub target: Vec<String>,
+
+    /// Path to Cargo.toml.
+    #[clap(long)]
+    pub manifest_path: Option<PathBuf>,
+
+    /// Directory for all generated artifacts.
+    #[clap(long)]
+    pub target_dir: Option<PathBuf>,
+
+    /// Cargo package to build.
+    #[clap(short, long)]
+    pub package: Option<String>,
+
+    /// Build the APK from the specified example instead of the library.
+    #[clap(long)]
+    pub example: Option<String>,
+
+    /// Run without accessing the network.
+    #[clap(long)]
+    pub offline: bool,
+
+    /// Number of targets to build concurrently. Cargo locks the target
+    /// directory for a whole build, so concurrent builds each use their own
+    /// under `target/apk/jobs`.
+    #[clap(long, default_value_t = 1)]
+    pub build_jobs: usize,
+
+    /// Build every target even if one fails, then report which failed.
+    #[clap(long)]
+    pub keep_going: bool,
+
+    /// Number of parallel jobs for each target build, defaults to the number
+    /// of CPUs shared between concurrent builds.
+    #[clap(short, long)]
+    pub jobs: Option<usize>,
+
+    /// Require `Cargo.lock` to be up to date.
+    #[clap(long)]
+    pub locked: bool,
+
+    /// Require `Cargo.lock` and the cache to be up to date, implies `--offline`.
+    #[clap(long)]
+    pub frozen: bool,
+
+    /// Override a cargo configuration value, passed to cargo as `--config`.
+    #[clap(long, value_name = "KEY=VALUE")]
+    pub config: Vec<String>,
+
+    /// Features to enable.
+    #[clap(short = 'F', long)]
+    pub features: Vec<String>,
+
+    /// The flavor from `package.metadata.apk.flavors` to build.
+    #[clap(long)]
+    pub flavor: Option<String>,
+
+    /// Do not activate the `default` feature.
+    #[clap(long)]
+    pub no_default_features: bool,
+
+    /// Activate all available features.
+    #[clap(long)]
+    pub all_features: bool,
+
+    /// Don't strip debug symbols from the library in release and custom
+    /// profile builds.
+    #[clap(long)]
+    pub no_strip: bool,
+
+    /// Archive the unstripped libraries as `target/apk/<package>-symbols.zip`,
+    /// for symbolicating crashes e.g. in the Play Console. `dev` builds aren't
+    /// stripped, so it requires `--release` or `--profile`.
+    #[clap(long, conflicts_with = "no_strip")]
+    pub symbols: bool,
+
+    /// Also write an APK per target, named `{package}-{abi}.apk`.
+    #[clap(long)]
+    pub split_abi: bool,
+
+    /// The page size native libraries are aligned to, 4096 or 16384, defaults
+    /// to 16384 for all but `dev` builds as required by Android 15.
+    #[clap(long)]
+    pub page_size: Option<u16>,
+
+    /// Skip the APK with every target when splitting by ABI.
+    #[clap(long, requires = "split_abi")]
+    pub no_universal: bool,
+
+    /// Print how long each phase of the build took.
+    #[clap(long)]
+    pub timings: bool,
+
+    /// Build with cargo instead of cross, the android linker must be
+    /// configured for each target.
+    #[clap(long)]
+    pub no_cross: bool,
+
+    /// The container engine used by cross, defaults to `CROSS_CONTAINER_ENGINE`.
+    #[clap(long, value_enum)]
+    pub container_engine: Option<ContainerEngine>,
+
+    /// Extra flags for rustc, split on whitespace like `RUSTFLAGS`. Added to
+    /// the configured `rustflags` of the target with `--config`, or appended
+    /// to `RUSTFLAGS` or `CARGO_ENCODED_RUSTFLAGS` when either is set.
+    #[clap(long, allow_hyphen_values = true)]
+    pub rustflags: Vec<String>,
+
+    /// The `rustflags` of the metadata by target, added by `Project::load`.
+    #[clap(skip)]
+    pub target_rustflags: BTreeMap<String, Vec<String>>,
+
+    /// Use verbose output, `-vv` also logs device queries, `RUST_LOG`
+    /// overrides both.
+    #[clap(short, long, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ContainerEngine {
+    Docker,
+    Podman,
+}
+
+impl ContainerEngine {
+    fn as_str(self) -> &'static str {
+        match self {
+            ContainerEngine::Docker => "docker",
+            ContainerEngine::Podman => "podman",
+        }
+    }
+}
+
+impl BuildOptions {
+    /// Use the `build.target` of cargo's configuration when no target is set.
+    fn default_targets(&mut self) {
+        if self.target.is_empty() {
+            self.target = cargo_config::build_target(&self.config);
+        }
+    }
+
+    /// Whether debug symbols are stripped from the library, for release and
+    /// custom profiles.
+    fn strips(&self) -> bool {
+        !self.is_debug() && !self.no_strip
+    }
+
+    /// Whether the artifact is built with the `dev` profile, custom profiles
+    /// are assumed to not be for debugging.
+    pub fn is_debug(&self) -> bool {
+        !self.release && matches!(self.profile.as_deref(), None | Some("dev"))
+    }
+
+    /// The page size native libraries are aligned to.
+    pub fn page_size(&self) -> u16 {
+        match self.page_size {
+            Some(page_size) => page_size,
+            None if !self.is_debug() => 16384,
+            None => 4096,
+        }
+    }
+
+    /// Whether the network must not be accessed.
+    pub fn is_offline(&self) -> bool {
+        self.offline || self.frozen
+    }
+
+    /// The flags shared by `cargo metadata` and `cargo build`.
+    fn cargo_flags(&self) -> Vec<String> {
+        let mut flags = Vec::new();
+
+        if self.offline {
+            flags.push(String::from("--offline"));
+        }
+
+        if self.locked {
+            flags.push(String::from("--locked"));
+        }
+
+        if self.frozen {
+            flags.push(String::from("--frozen"));
+        }
+
+        for config in self.config.iter() {
+            flags.push(String::from("--config"));
+            flags.push(config.clone());
+        }
+
+        flags
+    }
+
+    /// Whether `target` is the one selected by `--example` or the library by
+    /// default.
+    fn selects(&self, target: &cargo_metadata::Target) -> bool {
+        if let Some(ref example) = self.example {
+            target.is_example() && target.name == *example
+        } else {
+            target.kind.iter().any(|kind| {
+                matches!(
+                    kind.as_str(),
+                    "lib" | "rlib" | "dylib" | "cdylib" | "staticlib"
+                )
+            })
+        }
+    }
+
+    /// The name of the library loaded by the activity.
+    fn lib_name(&self, package: &cargo_metadata::Package) -> eyre::Result<String> {
+        let target = package
+            .targets
+            .iter()
+            .find(|target| self.selects(target))
+            .ok_or_else(|| eyre::eyre!("Package `{}` has no such target", package.name))?;
+
+        // checked up front, a missing cdylib is otherwise only noticed after
+        // compiling
+        if !target.crate_types.iter().any(|t| t == "cdylib") {
+            if target.is_example() {
+                eyre::bail!(
+                    "Example `{}` is not a cdylib, add `crate-type = [\"cdylib\"]` to its `[[example]]` in `{}`",
+                    target.name,
+                    package.manifest_path
+                );
+            } else {
+                eyre::bail!(
+                    "Library `{}` is not a cdylib, add `crate-type = [\"cdylib\"]` to `[lib]` in `{}`",
+                    target.name,
+                    package.manifest_path
+                );
+            }
+        }
+
+        Ok(target.name.replace('-', "_"))
+    }
+}
+
+#[derive(Clone, Parser)]
+pub struct InstallOptions {
+    #[clap(flatten)]
+    pub build: BuildOptions,
+
+    /// Grant all runtime permissions listed in the manifest.
+    #[clap(long)]
+    pub grant_permissions: bool,
+
+    /// Install on all connected devices.
+    #[clap(long)]
+    pub all_devices: bool,
+
+    /// Rebuild and reinstall whenever the package changes.
+    #[clap(long)]
+    pub watch: bool,
+
+    /// Seconds an adb command may take before it is killed.
+    #[clap(long, default_value_t = 30)]
+    pub adb_timeout: u64,
+
+    /// Seconds `adb install` may take before it is killed, longer than
+    /// `--adb-timeout` as large APKs take a while over USB.
+    #[clap(long, default_value_t = 600)]
+    pub install_timeout: u64,
+
+    /// Boot this Android Virtual Device and install on it.
+    #[clap(long, value_name = "AVD")]
+    pub emulator: Option<String>,
+
+    /// Shut down the emulator booted by `--emulator` when done, instead of
+    /// leaving it running.
+    #[clap(long, requires = "emulator", conflicts_with = "watch")]
+    pub kill_emulator: bool,
+
+    /// Install for this Android user only, as listed by `apk users`.
+    #[clap(long, value_name = "ID")]
+    pub user: Option<u32>,
+}
+
+impl InstallOptions {
+    /// Whether runtime permissions are granted, with `--grant-permissions`
+    /// or `--dev`.
+    pub fn grants_permissions(&self) -> bool {
+        self.grant_permissions || self.build.dev
+    }
+}
+
+#[derive(Parser)]
+pub struct RunOptions {
+    #[clap(flatten)]
+    pub install: InstallOptions,
+
+    /// The intent action to launch the activity with.
+    #[clap(long)]
+    pub launch_action: Option<String>,
+
+    /// The intent data URI to launch the activity with, e.g. a deep link.
+    #[clap(long)]
+    pub launch_data: Option<String>,
+
+    /// A string extra to launch the activity with, as `key=value`.
+    #[clap(long)]
+    pub launch_extra: Vec<String>,
+
+    /// Save a PNG screenshot to this path after launching, suffixed with the
+    /// device serial when installing on several devices.
+    #[clap(long)]
+    pub screenshot: Option<PathBuf>,
+
+    /// Seconds to wait after launching before taking the screenshot.
+    #[clap(long, default_value_t = 0, requires = "screenshot")]
+    pub screenshot_delay: u64,
+}
+
+#[derive(Parser)]
+pub struct DevicesOptions {
+    /// Seconds an adb command may take before it is killed.
+    #[clap(long, default_value_t = 30)]
+    pub adb_timeout: u64,
+}
+
+#[derive(Parser)]
+pub struct SdkOptions {
+    /// Path to Cargo.toml.
+    #[clap(long)]
+    pub manifest_path: Option<PathBuf>,
+
+    /// Directory for all generated artifacts.
+    #[clap(long)]
+    pub target_dir: Option<PathBuf>,
+
+    /// Cargo package whose NDK version to download.
+    #[clap(short, long)]
+    pub package: Option<String>,
+
+    /// The API level of the SDK platform to download.
+    #[clap(long, default_value_t = SDK_VERSION)]
+    pub sdk_version: u32,
+
+    /// The NDK version to download, defaults to the package's `ndk-version`.
+    #[clap(long)]
+    pub ndk_version: Option<String>,
+}
+
+#[derive(Parser)]
+pub struct PullOptions {
+    /// Path on the device, relative paths are in the app's data directory.
+    pub remote: String,
+
+    /// Path to write the file to.
+    pub local: PathBuf,
+
+    #[clap(flatten)]
+    pub build: BuildOptions,
+
+    /// Seconds an adb command may take before it is killed.
+    #[clap(long, default_value_t = 30)]
+    pub adb_timeout: u64,
+}
+
+#[derive(Parser)]
+pub struct EnvOptions {
+    /// Path to Cargo.toml.
+    #[clap(long)]
+    pub manifest_path: Option<PathBuf>,
+
+    /// Directory for all generated artifacts.
+    #[clap(long)]
+    pub target_dir: Option<PathBuf>,
+
+    /// Cargo package whose SDK and NDK to report.
+    #[clap(short, long)]
+    pub package: Option<String>,
+}
+
+#[derive(Parser)]
+pub struct DownloadPackageOptions {
+    /// Directory the package is extracted into.
+    pub install_dir: Utf8PathBuf,
+
+    /// The SDK manager name of the package, e.g. `ndk;26.1.10909125`.
+    pub package: String,
+
+    /// Only extract the file with this name.
+    #[clap(long)]
+    pub only: Option<String>,
+}
+
+#[derive(Parser)]
+pub struct UsersOptions {
+    /// Seconds an adb command may take before it is killed.
+    #[clap(long, default_value_t = 30)]
+    pub adb_timeout: u64,
+}
+
+#[derive(Parser)]
+pub struct ExportGradleOptions {
+    /// Directory to write the Gradle project to.
+    #[clap(long)]
+    pub out: PathBuf,
+
+    #[clap(flatten)]
+    pub build: BuildOptions,
+}
+
+#[derive(Parser)]
+pub struct CleanOptions {
+    /// Path to Cargo.toml.
+    #[clap(long)]
+    pub manifest_path: Option<PathBuf>,
+
+    /// Directory for all generated artifacts.
+    #[clap(long)]
+    pub target_dir: Option<PathBuf>,
+
+    /// Cargo package to clean.
+    #[clap(short, long)]
+    pub package: Option<String>,
+
+    /// Also remove the downloaded SDK and NDK.
+    #[clap(long)]
+    pub sdk: bool,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "kebab-case")]
+struct Metadata {
+    /// The code namespace of the APK.
+    package: Option<String>,
+
+    /// The install identity of the APK, defaults to `package`.
+    ///
+    /// The binary manifest has a single `package` attribute, which is the
+    /// application id, so `package` only matters when this is unset.
+    application_id: Option<String>,
+
+    /// Appended to the application id of debug builds, an empty string
+    /// disables it, defaults to `.debug`.
+    debug_application_id_suffix: Option<String>,
+
+    /// The version code of the APK.
+    version_code: Option<u32>,
+
+    /// The version name of the APK.
+    version_name: Option<String>,
+
+    /// Appended to the version name of debug builds, to tell them apart.
+    version_name_suffix: Option<VersionNameSuffix>,
+
+    /// The icon of the APK.
+    icon: Option<String>,
+
+    /// The application label, defaults to the ori name or the package name.
+    label: Option<String>,
+
+    /// The fully qualified class name of the main activity.
+    activity_name: Option<String>,
+
+    /// The application theme, either `@android:style/...` or `@style/...`.
+    theme: Option<String>,
+
+    /// The screen orientation of the main activity, e.g. `portrait`.
+    orientation: Option<String>,
+
+    /// The configuration changes the main activity handles itself, replacing
+    /// the default set, the activity is recreated on any other change.
+    config_changes: Option<Vec<String>>,
+
+    /// How the main activity is launched into tasks, defaults to `singleTop`.
+    launch_mode: Option<String>,
+
+    /// How the main activity reacts to the soft keyboard, e.g.
+    /// `stateHidden|adjustPan`, defaults to `adjustResize`.
+    window_soft_input_mode: Option<String>,
+
+    /// The NDK version to download, exposed as `ANDROID_NDK_HOME` to builds
+    /// with `--no-cross`, cross images bring their own NDK.
+    ndk_version: Option<String>,
+
+    /// Whether to compress native libraries in the APK, by default they are
+    /// stored uncompressed and page aligned. Setting
+    /// `android:extractNativeLibs="false"` isn't supported by the manifest, so
+    /// the installer extracts them either way and compressing only makes the
+    /// APK smaller.
+    #[serde(default)]
+    compress_native_libs: bool,
+
+    /// Extra flags for rustc by target triple, appended like `--rustflags`.
+    /// Each entry is a single flag and may contain spaces.
+    #[serde(default)]
+    rustflags: BTreeMap<String, Vec<String>>,
+
+    /// A partial `AndroidManifest.xml` merged into the generated manifest.
+    /// The result is validated like the generated values, and
+    /// `android:debuggable` is ignored for release builds.
+    manifest_overlay: Option<String>,
+
+    /// Values substituted for `${key}` placeholders in the manifest.
+    #[serde(default)]
+    manifest_placeholders: BTreeMap<String, String>,
+
+    #[serde(default)]
+    uses_feature: Vec<UsesFeature>,
+
+    #[serde(default)]
+    uses_permission: Vec<UsesPermission>,
+
+    /// Intent filters added to the main activity, next to the launcher one.
+    #[serde(default)]
+    intent_filters: Vec<IntentFilter>,
+
+    /// Android TV support, adds the leanback feature and launcher category.
+    #[serde(default)]
+    tv: bool,
+
+    /// `<meta-data>` entries on the application, e.g. API keys of libraries.
+    /// Values are strings, `@` resource references aren't supported.
+    #[serde(default)]
+    application_meta_data: BTreeMap<String, String>,
+
+    /// Render the activity with hardware acceleration, defaults to `true`.
+    hardware_accelerated: Option<bool>,
+
+    /// The release signing key, `--pem` takes precedence.
+    signing: Option<Signing>,
+
+    /// Activities next to the main one, e.g. a settings entry in the
+    /// launcher. The manifest has no `<activity-alias>`, so each needs its
+    /// own class.
+    #[serde(default)]
+    activities: Vec<ExtraActivity>,
+
+    /// Replaces the bundled `classes.dex`, `--dex` takes precedence.
+    classes_dex: Option<String>,
+
+    /// Extra dex files added as `classes2.dex`, `classes3.dex`, ... after
+    /// the bundled one, relative to the package.
+    #[serde(default)]
+    dex: Vec<String>,
+
+    /// Build variants overriding the metadata above, selected with `--flavor`.
+    #[serde(default)]
+    flavors: BTreeMap<String, Flavor>,
+}
+
+/// Signing configuration. Only PEM keys are supported, keystores have to be
+/// converted first.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "kebab-case")]
+struct Signing {
+    /// Path to the PEM encoded key and certificate, relative to the package.
+    /// May reference environment variables as `${VAR}`.
+    pem: String,
+}
+
+impl Signing {
+    /// The PEM to sign with, relative to `package_root`.
+    fn pem_path(&self, package_root: &Utf8Path) -> eyre::Result<PathBuf> {
+        let pem = placeholders::substitute(&self.pem, |key| env::var(key).ok())
+            .wrap_err("Failed to resolve `signing.pem` from the environment")?;
+
+        Ok(package_root.join(pem).into())
+    }
+}
+
+/// A `uses-feature` entry, either a bare feature name or a table.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum UsesFeature {
+    Name(String),
+    Feature(Feature),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "kebab-case")]
+struct Feature {
+    name: Option<String>,
+
+    /// Whether the application can't function without the feature.
+    required: Option<bool>,
+
+    /// The version of the feature, e.g. for `android.hardware.vulkan.level`.
+    version: Option<u32>,
+
+    /// The required OpenGL ES version, e.g. `"3.1"`.
+    gles_version: Option<String>,
+}
+
+impl UsesFeature {
+    fn to_manifest(&self) -> eyre::Result<apk::manifest::Feature> {
+        let feature = match self {
+            UsesFeature::Name(name) => apk::manifest::Feature {
+                name: Some(name.clone()),
+                required: None,
+                version: None,
+                opengles_version: None,
+            },
+            UsesFeature::Feature(feature) => apk::manifest::Feature {
+                name: feature.name.clone(),
+                required: feature.required,
+                version: feature.version,
+                opengles_version: match feature.gles_version {
+                    Some(ref version) => Some(parse_gles_version(version)?),
+                    None => None,
+                },
+            },
+        };
+
+        Ok(feature)
+    }
+}
+
+/// A `uses-permission` entry, either a bare permission name or a table.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum UsesPermission {
+    Name(String),
+    Permission(Permission),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "kebab-case")]
+struct Permission {
+    name: String,
+
+    /// The highest API level the permission is requested on.
+    max_sdk_version: Option<u32>,
+}
+
+impl UsesPermission {
+    fn to_manifest(&self) -> apk::manifest::Permission {
+        match self {
+            UsesPermission::Name(name) => apk::manifest::Permission {
+                name: name.clone(),
+                max_sdk_version: None,
+            },
+            UsesPermission::Permission(permission) => apk::manifest::Permission {
+                name: permission.name.clone(),
+                max_sdk_version: permission.max_sdk_version,
+            },
+        }
+    }
+}
+
+/// An activity in addition to the main Ori activity.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "kebab-case")]
+struct ExtraActivity {
+    /// The class implementing the activity.
+    name: String,
+
+    label: Option<String>,
+
+    /// Add an entry for the activity to the launcher.
+    #[serde(default)]
+    launcher: bool,
+
+    /// Defaults to `true` for activities with intent filters.
+    exported: Option<bool>,
+
+    #[serde(default)]
+    intent_filters: Vec<IntentFilter>,
+}
+
+impl ExtraActivity {
+    fn to_manifest(&self) -> eyre::Result<apk::manifest::Activity> {
+        if self.name.trim().is_empty() {
+            eyre::bail!("Activity names must not be empty");
+        }
+
+        let mut intent_filters = Vec::new();
+
+        if self.launcher {
+            intent_filters.push(apk::manifest::IntentFilter {
+                actions: vec![String::from("android.intent.action.MAIN")],
+                categories: vec![String::from("android.intent.category.LAUNCHER")],
+                ..Default::default()
+            });
+        }
+
+        for filter in self.intent_filters.iter() {
+            intent_filters.push(filter.to_manifest()?);
+        }
+
+        let exported = match self.exported {
+            Some(exported) => Some(exported),
+            None if !intent_filters.is_empty() => Some(true),
+            None => None,
+        };
+
+        Ok(apk::manifest::Activity {
+            name: Some(self.name.clone()),
+            label: self.label.clone(),
+            exported,
+            intent_filters,
+            ..Default::default()
+        })
+    }
+}
+
+/// An intent filter on the main activity, e.g. for deep links.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "kebab-case")]
+struct IntentFilter {
+    #[serde(default)]
+    actions: Vec<String>,
+
+    #[serde(default)]
+    categories: Vec<String>,
+
+    #[serde(default)]
+    data: Vec<IntentFilterData>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "kebab-case")]
+struct IntentFilterData {
+    scheme: Option<String>,
+    host: Option<String>,
+    port: Option<String>,
+    path: Option<String>,
+    path_prefix: Option<String>,
+    path_pattern: Option<String>,
+    mime_type: Option<String>,
+}
+
+impl IntentFilter {
+    fn to_manifest(&self) -> eyre::Result<apk::manifest::IntentFilter> {
+        if self.actions.is_empty() {
+            eyre::bail!("Intent filter requires at least one action");
+        }
+
+        let mut data = Vec::new();
+
+        for entry in self.data.iter() {
+            if entry.scheme.is_none() {
+                eyre::bail!(
+                    "Intent filter data for action `{}` requires a `scheme`",
+                    self.actions.join("`, `")
+                );
+            }
+
+            data.push(apk::manifest::IntentFilterData {
+                scheme: entry.scheme.clone(),
+                host: entry.host.clone(),
+                port: entry.port.clone(),
+                path: entry.path.clone(),
+                path_pattern: entry.path_pattern.clone(),
+                path_prefix: entry.path_prefix.clone(),
+                mime_type: entry.mime_type.clone(),
+            });
+        }
+
+        Ok(apk::manifest::IntentFilter {
+            actions: self.actions.clone(),
+            categories: self.categories.clone(),
+            data,
+        })
+    }
+}
+
+fn parse_gles_version(version: &str) -> eyre::Result<(u8, u8)> {
+    let (major, minor) = version.split_once('.').unwrap_or((version, "0"));
+
+    match (major.parse(), minor.parse()) {
+        (Ok(major), Ok(minor)) => Ok((major, minor)),
+        _ => eyre::bail!(
+            "Malformed OpenGL ES version `{}`, expected e.g. `3.1`",
+            version
+        ),
+    }
+}
+
+impl Metadata {
+    fn from_package(package: &cargo_metadata::Package) -> eyre::Result<Self> {
+        match package.metadata.get("apk") {
+            Some(value) => Ok(serde_json::from_value(value.clone())?),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Merge the flavor `name` over the base metadata, returning the cargo
+    /// features it enables.
+    fn apply_flavor(&mut self, name: &str) -> eyre::Result<Vec<String>> {
+        let flavor = match self.flavors.remove(name) {
+            Some(flavor) => flavor,
+            None => {
+                let flavors: Vec<_> = self.flavors.keys().map(String::as_str).collect();
+
+                eyre::bail!(
+                    "Unknown flavor `{}`, defined flavors are: {}",
+                    name,
+                    flavors.join(", ")
+                );
+            }
+        };
+
+        if flavor.application_id.is_some() {
+            self.application_id = flavor.application_id;
+        }
+
+        if flavor.label.is_some() {
+            self.label = flavor.label;
+        }
+
+        if flavor.icon.is_some() {
+            self.icon = flavor.icon;
+        }
+
+        Ok(flavor.features)
+    }
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum VersionNameSuffix {
+    /// The UTC build time, e.g. `20240607.153012`.
+    Timestamp,
+
+    /// The short hash of the checked out git commit.
+    GitHash,
+}
+
+impl VersionNameSuffix {
+    fn suffix(self, package_root: &Path) -> eyre::Result<String> {
+        match self {
+            VersionNameSuffix::Timestamp => {
+                let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+                Ok(utc_timestamp(now.as_secs()))
+            }
+            VersionNameSuffix::GitHash => {
+                let output = process::Command::new("git")
+                    .arg("rev-parse")
+                    .arg("--short")
+                    .arg("HEAD")
+                    .current_dir(package_root)
+                    .output()
+                    .wrap_err("Failed to run git")?;
+
+                if !output.status.success() {
+                    eyre::bail!("`git rev-parse` failed, is the package in a git repository?");
+                }
+
+                Ok(String::from_utf8(output.stdout)?.trim().to_owned())
+            }
+        }
+    }
+}
+
+/// Format seconds since the unix epoch as `YYYYMMDD.HHMMSS` in UTC.
+fn utc_timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let time = secs % 86400;
+
+    // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}{:02}{:02}.{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// A build variant, selected with `--flavor`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "kebab-case")]
+struct Flavor {
+    application_id: Option<String>,
+    label: Option<String>,
+    icon: Option<String>,
+
+    /// Cargo features enabled in addition to `--features`.
+    #[serde(default)]
+    features: Vec<String>,
+}
+
+struct Device {
+    id: String,
+    arch: apk::Target,
+    model: Option<String>,
+    device: Option<String>,
+}
+
+impl Device {
+    fn target_triple(&self) -> &'static str {
+        target_triple(self.arch)
+    }
+
+    /// The serial, followed by the model if adb reports one.
+    fn display_name(&self) -> String {
+        match self.model.as_ref().or(self.device.as_ref()) {
+            Some(model) => format!("`{}` ({})", self.id, model),
+            None => format!("`{}`", self.id),
+        }
+    }
+}
+
+fn target_triple(arch: apk::Target) -> &'static str {
+    match arch {
+        apk::Target::Arm64V8a => "aarch64-linux-android",
+        apk::Target::ArmV7a => "armv7-linux-androideabi",
+        apk::Target::X86 => "i686-linux-android",
+        apk::Target::X86_64 => "x86_64-linux-android",
+    }
+}
+
+/// A line of `adb devices -l`.
+struct AdbDevice {
+    id: String,
+    state: String,
+    model: Option<String>,
+    device: Option<String>,
+}
+
+/// Every device known to adb, including offline and unauthorized ones.
+fn adb_devices() -> eyre::Result<Vec<AdbDevice>> {
+    let output = adb_output(
+        process::Command::new("adb").arg("devices").arg("-l"),
+        Level::TRACE,
+    )?;
+
+    let mut devices = Vec::new();
+
+    let mut lines = output.stdout.lines();
+    lines.next();
+
+    for line in lines {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut parts = line.split_whitespace();
+
+        let (id, state) = match (parts.next(), parts.next()) {
+            (Some(id), Some(state)) => (String::from(id), String::from(state)),
+            _ => eyre::bail!("Malformed adb output"),
+        };
+
+        let mut device = AdbDevice {
+            id,
+            state,
+            model: None,
+            device: None,
+        };
+
+        // the rest are `key:value` pairs, e.g. `model:Pixel_7 device:panther`
+        for part in parts {
+            match part.split_once(':') {
+                Some(("model", model)) => device.model = Some(String::from(model)),
+                Some(("device", name)) => device.device = Some(String::from(name)),
+                _ => {}
+            }
+        }
+
+        devices.push(device);
+    }
+
+    Ok(devices)
+}
+
+fn get_device_arch(id: &str) -> eyre::Result<apk::Target> {
+    let mut command = process::Command::new("adb");
+
+    command
+        .arg("-s")
+        .arg(id)
+        .arg("shell")
+        .arg("getprop")
+        .arg("ro.product.cpu.abi");
+
+    let output = adb_output(&mut command, Level::TRACE)?;
+
+    let arch = String::from_utf8(output.stdout)?;
+
+    match arch.trim() {
+        "arm64-v8a" => Ok(apk::Target::Arm64V8a),
+        "armeabi-v7a" => Ok(apk::Target::ArmV7a),
+        "x86_64" => Ok(apk::Target::X86_64),
+        "x86" => Ok(apk::Target::X86),
+        _ => eyre::bail!("Unknown abi `{}`", arch.trim()),
+    }
+}
+
+/// Wait with `adb wait-for-device` until the device `id`, or any device, is
+/// online, and then until it has finished booting.
+fn wait_for_device(id: Option<&str>, timeout: Duration) -> eyre::Result<()> {
+    let start = Instant::now();
+    let name = id.unwrap_or("any device");
+
+    let mut command = process::Command::new("adb");
+
+    if let Some(id) = id {
+        command.arg("-s").arg(id);
+    }
+
+    command
+        .arg("wait-for-device")
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::null())
+        .stderr(process::Stdio::null());
+
+    echo_command(&command, Level::DEBUG);
+
+    let mut child = command.spawn()?;
+
+    while child.try_wait()?.is_none() {
+        if start.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+
+            eyre::bail!(
+                "`{}` did not come online within {}s, use `--adb-timeout` to wait longer",
+                name,
+                timeout.as_secs()
+            );
+        }
+
+        thread::sleep(ADB_POLL_INTERVAL);
+    }
+
+    let Some(id) = id else {
+        return Ok(());
+    };
+
+    loop {
+        let mut command = process::Command::new("adb");
+
+        command
+            .arg("-s")
+            .arg(id)
+            .arg("shell")
+            .arg("getprop")
+            .arg("sys.boot_completed");
+
+        let output = adb_output(&mut command, Level::TRACE)?;
+
+        if String::from_utf8_lossy(&output.stdout).trim() == "1" {
+            return Ok(());
+        }
+
+        if start.elapsed() >= timeout {
+            eyre::bail!(
+                "`{}` did not finish booting within {}s, use `--adb-timeout` to wait longer",
+                id,
+                timeout.as_secs()
+            );
+        }
+
+        thread::sleep(BOOT_POLL_INTERVAL);
+    }
+}
+
+/// The devices that are online and authorized.
+fn get_devices() -> eyre::Result<Vec<Device>> {
+    let mut devices = Vec::new();
+
+    for device in adb_devices()? {
+        if device.state != "device" {
+            continue;
+        }
+
+        devices.push(Device {
+            arch: get_device_arch(&device.id)?,
+            id: device.id,
+            model: device.model,
+            device: device.device,
+        });
+    }
+
+    Ok(devices)
+}
+
+fn list_devices() -> eyre::Result<()> {
+    ensure_adb_installed()?;
+
+    let mut rows = Vec::new();
+
+    for device in adb_devices()? {
+        let (abi, triple) = if device.state == "device" {
+            let arch = get_device_arch(&device.id)?;
+            (arch.android_abi(), target_triple(arch))
+        } else {
+            ("-", "-")
+        };
+
+        let model = device.model.or(device.device);
+
+        rows.push([
+            device.id,
+            device.state,
+            model.unwrap_or_else(|| String::from("-")),
+            String::from(abi),
+            String::from(triple),
+        ]);
+    }
+
+    if rows.is_empty() {
+        status!("  {} no devices connected", "Devices".green().bold());
+        return Ok(());
+    }
+
+    let header = ["SERIAL", "STATE", "MODEL", "ABI", "TARGET"].map(String::from);
+
+    let mut widths = [0; 5];
+
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        for (width, column) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(column.len());
+        }
+    }
+
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        let mut line = String::new();
+
+        for (column, width) in row.iter().zip(widths) {
+            line += &format!("{:width$}  ", column);
+        }
+
+        status!("{}", line.trim_end());
+    }
+
+    Ok(())
+}
+
+fn list_users(global: &GlobalOptions) -> eyre::Result<()> {
+    ensure_adb_installed()?;
+
+    let devices = selected_devices(global)?;
+    check_device_selection(&devices, None, global)?;
+    let device = &devices[0];
+
+    let mut command = process::Command::new("adb");
+
+    command
+        .arg("-s")
+        .arg(&device.id)
+        .arg("shell")
+        .arg("pm")
+        .arg("list")
+        .arg("users");
+
+    let output = adb_output(&mut command, Level::DEBUG)?;
+
+    if !output.status.success() {
+        eyre::bail!(
+            "Listing users on `{}` failed: {}",
+            device.id,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    // users are listed as `UserInfo{<id>:<name>:<flags>}`, followed by
+    // `running` for started users
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    status!("ID  NAME");
+
+    for line in stdout.lines() {
+        let line = line.trim();
+
+        let Some(info) = line
+            .strip_prefix("UserInfo{")
+            .and_then(|rest| rest.split_once('}'))
+        else {
+            continue;
+        };
+
+        let mut fields = info.0.split(':');
+        let id = fields.next().unwrap_or_default();
+        let name = fields.next().unwrap_or_default();
+
+        let running = if info.1.contains("running") {
+            " (running)"
+        } else {
+            ""
+        };
+
+        status!("{:2}  {}{}", id, name, running);
+    }
+
+    Ok(())
+}
+
+/// The first API level supporting `adb install -g`.
+const GRANT_PERMISSIONS_API_LEVEL: u32 = 23;
+
+fn launch_app(
+    device: &Device,
+    manifest: &apk::AndroidManifest,
+    options: &RunOptions,
+) -> eyre::Result<()> {
+    let package = manifest
+        .package
+        .as_deref()
+        .ok_or_else(|| eyre::eyre!("Manifest has no package"))?;
+
+    let activity = manifest
+        .application
+        .activities
+        .first()
+        .and_then(|activity| activity.name.as_deref())
+        .ok_or_else(|| eyre::eyre!("Manifest has no activity"))?;
+
+    status!(
+        "  {} launching `{}` on `{}`",
+        "Run".green().bold(),
+        package,
+        device.id
+    );
+
+    // `-S` stops the app first, so a running instance is relaunched
+    let mut command = process::Command::new("adb");
+
+    command
+        .arg("-s")
+        .arg(&device.id)
+        .arg("shell")
+        .arg("am")
+        .arg("start")
+        .arg("-S")
+        .arg("-n")
+        .arg(format!("{}/{}", package, activity));
+
+    if let Some(user) = options.install.user {
+        command.arg("--user").arg(user.to_string());
+    }
+
+    if let Some(ref action) = options.launch_action {
+        command.arg("-a").arg(shell_quote(action));
+    }
+
+    if let Some(ref data) = options.launch_data {
+        command.arg("-d").arg(shell_quote(data));
+    }
+
+    for extra in options.launch_extra.iter() {
+        let (key, value) = extra.split_once('=').ok_or_else(|| {
+            eyre::eyre!("Malformed launch extra `{}`, expected `key=value`", extra)
+        })?;
+
+        command
+            .arg("--es")
+            .arg(shell_quote(key))
+            .arg(shell_quote(value));
+    }
+
+    let output = adb_output(&mut command, Level::DEBUG)?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    if !output.status.success() || stdout.contains("Error:") {
+        eyre::bail!("Launching `{}` failed", package);
+    }
+
+    Ok(())
+}
+
+/// Quote `arg` for the device shell, `adb shell` joins its arguments with
+/// spaces and runs them through `sh`.
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+fn get_api_level(device: &Device) -> eyre::Result<u32> {
+    let mut command = process::Command::new("adb");
+
+    command
+        .arg("-s")
+        .arg(&device.id)
+        .arg("shell")
+        .arg("getprop")
+        .arg("ro.build.version.sdk");
+
+    let output = adb_output(&mut command, Level::TRACE)?;
+
+    let api_level = String::from_utf8(output.stdout)?;
+
+    api_level
+        .trim()
+        .parse()
+        .wrap_err_with(|| format!("Malformed API level `{}`", api_level.trim()))
+}
+
+fn get_package<'a>(
+    metadata: &'a cargo_metadata::Metadata,
+    name: Option<&str>,
+) -> eyre::Result<&'a cargo_metadata::Package> {
+    match name {
+        Some(ref package) => metadata
+            .packages
+            .iter()
+            .find(|p| p.name == *package)
+            .ok_or_else(|| eyre::eyre!("Package `{}` not found", package)),
+        None => {
+            if let Some(package) = metadata.root_package() {
+                return Ok(package);
+            }
+
+            // virtual workspaces have no root package, fall back to the
+            // default members, `workspace_default_members` requires cargo 1.71
+            let default_members = &metadata.workspace_default_members;
+
+            let members = if cargo_metadata::workspace_default_members_is_missing(default_members) {
+                metadata.workspace_packages()
+            } else {
+                metadata.workspace_default_packages()
+            };
+
+            match members.as_slice() {
+                [package] => Ok(package),
+                [] => eyre::bail!("No package"),
+                _ => {
+                    let names = members
+                        .iter()
+                        .map(|member| format!("`{}`", member.name))
+                        .collect::<Vec<_>>();
+
+                    eyre::bail!(
+                        "Multiple packages found, use `--package` to select one of {}",
+                        names.join(", ")
+                    )
+                }
+            }
+        }
+    }
+}
+
+fn install_apk(
+    project: &Project,
+    devices: &[Device],
+    options: &InstallOptions,
+    global: &GlobalOptions,
+) -> eyre::Result<()> {
+    ensure_adb_installed()?;
+
+    let built = build_apk(project, &options.build, global)?;
+    let manifest = &project.manifest;
+
+    if global.dry_run {
+        for device in devices {
+            let mut flags = String::new();
+
+            if options.grants_permissions() {
+                flags += " -g";
+            }
+
+            if let Some(user) = options.user {
+                flags += &format!(" --user {}", user);
+            }
+
+            status!(
+                "  {} install{} on {}",
+                "Plan".green().bold(),
+                flags,
+                device.display_name()
+            );
+        }
+
+        return Ok(());
+    }
+
+    let start = Instant::now();
+
+    if let [device] = devices {
+        let apk = built.for_arch(device.arch)?;
+        let result = install_on_device(device, apk, manifest, options, global);
+        timings::record("install", start);
+
+        return result;
+    }
+
+    let mut results = Vec::new();
+
+    for device in devices {
+        let result = built
+            .for_arch(device.arch)
+            .and_then(|apk| install_on_device(device, apk, manifest, options, global));
+
+        if let Err(ref err) = result {
+            status!(
+                "  {} install on `{}` failed: {}",
+                "Error".red().bold(),
+                device.id,
+                err
+            );
+        }
+
+        results.push((&device.id, result.is_ok()));
+    }
+
+    timings::record("install", start);
+
+    for (id, success) in results.iter() {
+        if *success {
+            status!("     {} `{}`", "Success".green().bold(), id);
+        } else {
+            status!("      {} `{}`", "Failed".red().bold(), id);
+        }
+    }
+
+    let failed = results.iter().filter(|(_, success)| !success).count();
+
+    if failed > 0 {
+        eyre::bail!("Install failed on {} of {} devices", failed, devices.len());
+    }
+
+    Ok(())
+}
+
+fn install_on_device(
+    device: &Device,
+    apk_path: &Path,
+    manifest: &apk::AndroidManifest,
+    options: &InstallOptions,
+    global: &GlobalOptions,
+) -> eyre::Result<()> {
+    let _span = tracing::info_span!("install", device = %device.id).entered();
+
+    let mut grant_permissions = options.grants_permissions();
+
+    if grant_permissions {
+        let api_level = get_api_level(device)?;
+
+        if api_level < GRANT_PERMISSIONS_API_LEVEL {
+            status!(
+                "  {} device API level {} does not support granting permissions on install",
+                "Warning".yellow().bold(),
+                api_level,
+            );
+
+            grant_permissions = false;
+        }
+    }
+
+    status!(
+        "  {} installing APK on `{}`",
+        "Install".green().bold(),
+        device.id
+    );
+
+    let result = adb_install_retrying(
+        device,
+        apk_path,
+        manifest,
+        grant_permissions,
+        options.user,
+        global,
+    );
+
+    crate::emit(&crate::Message::InstallFinished {
+        device: &device.id,
+        success: result.is_ok(),
+    });
+
+    result?;
+
+    status!("    {} APK installed", "Finished".green().bold());
+
+    Ok(())
+}
+
+fn adb_install_retrying(
+    device: &Device,
+    apk_path: &Path,
+    manifest: &apk::AndroidManifest,
+    grant_permissions: bool,
+    user: Option<u32>,
+    global: &GlobalOptions,
+) -> eyre::Result<()> {
+    let (code, reason) = match adb_install(device, apk_path, grant_permissions, user)? {
+        AdbInstall::Success => return Ok(()),
+        AdbInstall::Failure(code) if is_signature_mismatch(&code) => {
+            (code, String::from("is signed with a different key"))
+        }
+        AdbInstall::Failure(code) if code == "INSTALL_FAILED_VERSION_DOWNGRADE" => {
+            let reason = format!(
+                "has a higher version code than {}, bump `version-code` in \
+                `[package.metadata.apk]` to keep its data",
+                manifest.version_code.unwrap_or(1),
+            );
+
+            (code, reason)
+        }
+        AdbInstall::Failure(code) => eyre::bail!("Install failed with `{}`", code),
+    };
+
+    let package = manifest
+        .package
+        .as_deref()
+        .ok_or_else(|| eyre::eyre!("Install failed with `{}`", code))?;
+
+    status!(
+        "  {} installed package `{}` {}",
+        "Warning".yellow().bold(),
+        package,
+        reason,
+    );
+
+    let action = format!("Uninstalling `{}` from `{}`", package, device.id);
+
+    if !crate::confirm(
+        global,
+        "Do you want to uninstall it and retry? [Y/n]",
+        &action,
+    )? {
+        eyre::bail!("Install failed with `{}`", code);
+    }
+
+    adb_uninstall(device, package)?;
+
+    if let AdbInstall::Failure(code) = adb_install(device, apk_path, grant_permissions, user)? {
+        eyre::bail!("Install failed with `{}`", code);
+    }
+
+    Ok(())
+}
+
+enum AdbInstall {
+    Success,
+    Failure(String),
+}
+
+fn adb_install(
+    device: &Device,
+    apk_path: &Path,
+    grant_permissions: bool,
+    user: Option<u32>,
+) -> eyre::Result<AdbInstall> {
+    let mut command = process::Command::new("adb");
+
+    command.arg("-s").arg(&device.id).arg("install").arg("-r");
+
+    if grant_permissions {
+        command.arg("-g");
+    }
+
+    if let Some(user) = user {
+        command.arg("--user").arg(user.to_string());
+    }
+
+    // large APKs take a while to push over USB
+    let timeout = Duration::from_secs(INSTALL_TIMEOUT.load(Ordering::Relaxed));
+    let output = adb_output_within(
+        command.arg(apk_path),
+        Level::DEBUG,
+        timeout,
+        "--install-timeout",
+    )?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    // adb reports failures as `Failure [INSTALL_FAILED_*: ...]`, on older
+    // versions with a successful exit status
+    if let Some(code) = install_failure_code(&stdout).or_else(|| install_failure_code(&stderr)) {
+        return Ok(AdbInstall::Failure(code));
+    }
+
+    if !output.status.success() {
+        let message = match stderr.trim() {
+            "" => "unknown error",
+            message => message,
+        };
+
+        return Ok(AdbInstall::Failure(String::from(message)));
+    }
+
+    Ok(AdbInstall::Success)
+}
+
+fn install_failure_code(output: &str) -> Option<String> {
+    let start = output
+        .find("INSTALL_FAILED_")
+        .or_else(|| output.find("INSTALL_PARSE_FAILED_"))?;
+
+    let code = output[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+        .collect();
+
+    Some(code)
+}
+
+fn is_signature_mismatch(code: &str) -> bool {
+    matches!(
+        code,
+        "INSTALL_FAILED_UPDATE_INCOMPATIBLE" | "INSTALL_FAILED_SHARED_USER_INCOMPATIBLE"
+    )
+}
+
+fn adb_uninstall(device: &Device, package: &str) -> eyre::Result<()> {
+    status!(
+        "  {} uninstalling package `{}`",
+        "Uninstall".green().bold(),
+        package
+    );
+
+    let mut command = process::Command::new("adb");
+
+    command
+        .arg("-s")
+        .arg(&device.id)
+        .arg("uninstall")
+        .arg(package);
+
+    let output = adb_output(&mut command, Level::DEBUG)?;
+
+    if !output.status.success() {
+        eyre::bail!("Uninstall of `{}` failed", package);
+    }
+
+    Ok(())
+}
+
+fn build_apk(
+    project: &Project,
+    options: &BuildOptions,
+    global: &GlobalOptions,
+) -> eyre::Result<BuiltApks> {
+    let _span = tracing::info_span!("apk", package = %project.package.name).entered();
+
+    let Project {
+        metadata,
+        package,
+        apk_metadata,
+        manifest,
+        ..
+    } = project;
+
+    if options.target.is_empty() {
+        eyre::bail!(
+            "Target not specified, use `--target` or set `build.target` in `.cargo/config.toml`"
+        );
+    }
+
+    check_targets(&options.target)?;
+
+    // the APK of a build that isn't stripped already has every symbol
+    if options.symbols && !options.strips() {
+        eyre::bail!(
+            "`--symbols` requires a release or custom profile build, the libraries of `dev` builds aren't stripped"
+        );
+    }
+
+    let pem_path = project.pem_path(options)?;
+
+    // the bundled key is public, anything signed with it can be replaced
+    if pem_path.is_none() && !options.is_debug() {
+        eyre::bail!("Release builds must not be signed with the debug key, use `--pem` or `signing.pem` to sign with a release key");
+    }
+
+    let page_size = check_page_size(options, manifest)?;
+
+    let icon_path = project.icon_path()?;
+    let classes_dex = project.classes_dex_path(options)?;
+    let extra_dex = project.extra_dex_paths()?;
+
+    if global.dry_run {
+        return print_build_plan(
+            project,
+            options,
+            icon_path.as_deref(),
+            classes_dex.as_deref(),
+            &extra_dex,
+            pem_path.as_deref(),
+        );
+    }
+
+    let ndk_path = prepare_toolchain(project, options, global)?;
+    let mut libs = build_libs(metadata, package, options, ndk_path.as_deref())?;
+
+    let sdk_path = prepare_sdk(&metadata.target_directory, options)?;
+    let dex_path = prepare_dex(classes_dex, &sdk_path)?;
+    let unstripped_libs = libs.clone();
+    let summary_libs = strip_libs(&mut libs, options, ndk_path.as_deref())?;
+
+    if options.symbols {
+        let symbols_path = symbols_path(&metadata.target_directory, &package.name);
+        write_symbols(&symbols_path, &unstripped_libs)?;
+    }
+
+    let signer = load_signer(pem_path.as_deref())?;
+    let fingerprint = certificate_fingerprint(signer.cert())?;
+    tracing::debug!(%fingerprint, "signing APK");
+
+    crate::emit(&crate::Message::ApkSigned {
+        fingerprint: &fingerprint,
+    });
+
+    let contents = ApkContents {
+        manifest,
+        icon_path: icon_path.as_deref(),
+        sdk_path: &sdk_path,
+        dex_path: &dex_path,
+        extra_dex: &extra_dex,
+        compress_native_libs: apk_metadata.compress_native_libs,
+        page_size,
+    };
+
+    // next to the library as built, not its stripped copy
+    let built_paths: Vec<&Utf8Path> = unstripped_libs
+        .iter()
+        .map(|(_, path)| path.as_path())
+        .collect();
+    let universal_path =
+        universal_apk_path(&metadata.target_directory, &package.name, &built_paths);
+
+    let built = assemble_apks(
+        metadata,
+        package,
+        options,
+        &contents,
+        &libs,
+        &universal_path,
+        &signer,
+    )?;
+    let summary_apks = record_checksums(&built, options)?;
+
+    let summary = BuildSummary {
+        package: &package.name,
+        targets: &options.target,
+        libs: summary_libs,
+        sdk_version: SDK_VERSION,
+        ndk_version: apk_metadata.ndk_version.as_deref(),
+        signer_fingerprint: &fingerprint,
+        manifest: SummaryManifest {
+            package: manifest.package.as_deref(),
+            version_code: manifest.version_code,
+            version_name: manifest.version_name.as_deref(),
+        },
+        apks: summary_apks,
+    };
+
+    write_build_summary(&metadata.target_directory, &summary)?;
+
+    for (_, path) in built.iter() {
+        crate::emit(&crate::Message::BuildFinished { apk: path });
+    }
+
+    status!("    {} APK built", "Finished".green().bold());
+
+    Ok(built)
+}
+
+/// The page size of `options`, warning when it is too small for the target
+/// SDK of `manifest`.
+fn check_page_size(options: &BuildOptions, manifest: &apk::AndroidManifest) -> eyre::Result<u16> {
+    let page_size = options.page_size();
+
+    if page_size != 4096 && page_size != 16384 {
+        eyre::bail!("Page size must be 4096 or 16384, got {}", page_size);
+    }
+
+    // 16KB page devices can't load libraries aligned to 4KB
+    let target_sdk = manifest.sdk.target_sdk_version.unwrap_or_default();
+
+    if page_size < 16384 && target_sdk >= 35 {
+        status!(
+            "  {} targeting API {} with {} byte pages, use `--page-size 16384` for Android 15 devices",
+            "Warning".yellow().bold(),
+            target_sdk,
+            page_size
+        );
+    }
+
+    Ok(page_size)
+}
+
+/// Download the SDK platform to `target_directory` and verify it against
+/// `--sdk-sha256`.
+fn prepare_sdk(target_directory: &Utf8Path, options: &BuildOptions) -> eyre::Result<PathBuf> {
+    let start = Instant::now();
+    let sdk_path = download_android_sdk(target_directory, SDK_VERSION, options.is_offline())?;
+
+    if let Some(ref expected) = options.sdk_sha256 {
+        verify_sha256(&sdk_path, expected)?;
+    }
+
+    timings::record("sdk", start);
+
+    Ok(sdk_path)
+}
+
+/// The `classes.dex` to add, `classes_dex` if set, or else the bundled one
+/// written next to the SDK at `sdk_path`.
+fn prepare_dex(classes_dex: Option<PathBuf>, sdk_path: &Path) -> eyre::Result<PathBuf> {
+    if let Some(dex_path) = classes_dex {
+        status!(
+            "   {} using dex `{}`",
+            "Building".green().bold(),
+            dex_path.display()
+        );
+
+        return Ok(dex_path);
+    }
+
+    let dex_path = sdk_path
+        .parent()
+        .expect("sdk_path has parent")
+        .join("classes.dex");
+
+    let start = Instant::now();
+    tracing::debug!(path = %dex_path.display(), "writing classes.dex");
+    fs::write(&dex_path, CLASSES_DEX).wrap_err("Failed to write classes.dex")?;
+    timings::record("write dex", start);
+
+    Ok(dex_path)
+}
+
+/// Strip `libs` in place unless built with `dev`, once so every APK gets the
+/// same libraries, returning their build summary entries.
+fn strip_libs(
+    libs: &mut [(apk::Target, Utf8PathBuf)],
+    options: &BuildOptions,
+    ndk_path: Option<&Path>,
+) -> eyre::Result<Vec<SummaryLib>> {
+    let start = Instant::now();
+    let mut summary_libs = Vec::new();
+
+    for (apk_target, lib_path) in libs.iter_mut() {
+        if options.strips() {
+            *lib_path = strip_lib(lib_path, ndk_path, options.verbose > 0)?;
+        }
+
+        summary_libs.push(SummaryLib {
+            abi: apk_target.android_abi(),
+            sha256: file_sha256(lib_path.as_ref())?,
+            path: lib_path.clone(),
+        });
+    }
+
+    timings::record("strip", start);
+
+    Ok(summary_libs)
+}
+
+fn symbols_path(target_directory: &Utf8Path, package_name: &str) -> Utf8PathBuf {
+    target_directory
+        .join("apk")
+        .join(format!("{}-symbols.zip", package_name))
+}
+
+/// Write the libraries `libs` to the zip at `path`, as `<abi>/<lib>.so` like
+/// the Play Console expects native debug symbols.
+fn write_symbols(path: &Utf8Path, libs: &[(apk::Target, Utf8PathBuf)]) -> eyre::Result<()> {
+    let start = Instant::now();
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut zip = xcommon::Zip::new(path.as_ref(), true).map_err(|e| eyre::eyre!("{}", e))?;
+
+    for (apk_target, lib_path) in libs {
+        let file_name = lib_path.file_name().expect("lib_path has file name");
+        let name = Path::new(apk_target.android_abi()).join(file_name);
+
+        zip.add_file(lib_path.as_ref(), &name, ZipFileOptions::Compressed)
+            .map_err(|e| eyre::eyre!("{}", e))?;
+    }
+
+    zip.finish().map_err(|e| eyre::eyre!("{}", e))?;
+    timings::record("symbols", start);
+
+    status!("     {} symbols `{}`", "Written".green().bold(), path);
+
+    Ok(())
+}
+
+/// The signer of the key at `pem_path`, or of the debug key.
+fn load_signer(pem_path: Option<&Path>) -> eyre::Result<apk::Signer> {
+    let pem = match pem_path {
+        Some(pem) => {
+            status!(
+                "   {} signing APK with pem `{}`",
+                "Building".green().bold(),
+                pem.display()
+            );
+
+            fs::read_to_string(pem).wrap_err("Failed to load PEM file")?
+        }
+        None => {
+            status!(
+                "   {} signing APK with debug pem",
+                "Building".green().bold()
+            );
+
+            String::from(include_str!("debug.pem"))
+        }
+    };
+
+    apk::Signer::new(&pem).map_err(|e| eyre::eyre!("{}", e))
+}
+
+/// Where the universal APK is written, next to the library of a single
+/// target at `lib_paths`, or in `target/apk` for several targets.
+fn universal_apk_path(
+    target_directory: &Utf8Path,
+    package_name: &str,
+    lib_paths: &[&Utf8Path],
+) -> Utf8PathBuf {
+    let apk_name = format!("{}.apk", package_name);
+
+    match lib_paths {
+        [lib_path] => lib_path
+            .parent()
+            .expect("lib_path has parent")
+            .join(apk_name),
+        // a fat APK doesn't belong to any single target directory
+        _ => target_directory.join("apk").join(apk_name),
+    }
+}
+
+/// Write the universal APK to `universal_path` and, with `--split-abi`, an
+/// APK per target.
+fn assemble_apks(
+    metadata: &cargo_metadata::Metadata,
+    package: &cargo_metadata::Package,
+    options: &BuildOptions,
+    contents: &ApkContents,
+    libs: &[(apk::Target, Utf8PathBuf)],
+    universal_path: &Utf8Path,
+    signer: &apk::Signer,
+) -> eyre::Result<BuiltApks> {
+    let mut built = BuiltApks::default();
+
+    if !options.no_universal {
+        if let Some(parent) = universal_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let apk_path: PathBuf = universal_path.into();
+        write_apk(contents, &apk_path, libs, signer)?;
+        built.universal = Some(apk_path);
+    }
+
+    if options.split_abi {
+        let apk_dir = metadata.target_directory.join("apk");
+        fs::create_dir_all(&apk_dir)?;
+
+        for (apk_target, lib_path) in libs {
+            let split_path: PathBuf = apk_dir
+                .join(format!("{}-{}.apk", package.name, apk_target.android_abi()))
+                .into();
+
+            write_apk(
+                contents,
+                &split_path,
+                &[(*apk_target, lib_path.clone())],
+                signer,
+            )?;
+
+            built.splits.push((*apk_target, split_path));
+        }
+    }
+
+    Ok(built)
+}
+
+/// Print the checksum of every APK in `built`, writing them to
+/// `--checksum-file`, and return their build summary entries.
+fn record_checksums<'a>(
+    built: &'a BuiltApks,
+    options: &BuildOptions,
+) -> eyre::Result<Vec<SummaryApk<'a>>> {
+    let mut summary_apks = Vec::new();
+    let mut checksums = String::new();
+
+    for (abi, path) in built.iter() {
+        let sha256 = file_sha256(path)?;
+
+        status!(
+            "    {} SHA-256 {} `{}`",
+            "Checksum".green().bold(),
+            sha256,
+            path.display()
+        );
+
+        checksums += &format!("{}  {}\n", sha256, path.display());
+
+        summary_apks.push(SummaryApk {
+            abi: abi.map(apk::Target::android_abi),
+            path,
+            sha256,
+        });
+    }
+
+    if let Some(ref checksum_file) = options.checksum_file {
+        fs::write(checksum_file, checksums).wrap_err_with(|| {
+            format!("Failed to write checksum to `{}`", checksum_file.display())
+        })?;
+    }
+
+    Ok(summary_apks)
+}
+
+/// The APKs written by `build_apk`.
+#[derive(Default)]
+struct BuiltApks {
+    /// The APK with the libraries of every target, unless `--no-universal`.
+    universal: Option<PathBuf>,
+
+    /// The APK of each target with `--split-abi`.
+    splits: Vec<(apk::Target, PathBuf)>,
+}
+
+impl BuiltApks {
+    /// Every APK, with the target of the splits.
+    fn iter(&self) -> impl Iterator<Item = (Option<apk::Target>, &Path)> {
+        let universal = self.universal.iter().map(|path| (None, path.as_path()));
+        let splits = self
+            .splits
+            .iter()
+            .map(|(target, path)| (Some(*target), path.as_path()));
+
+        universal.chain(splits)
+    }
+
+    /// The APK to install on a device of `arch`, its split if there is one.
+    fn for_arch(&self, arch: apk::Target) -> eyre::Result<&Path> {
+        let split = self.splits.iter().find(|(target, _)| *target == arch);
+
+        match (split, &self.universal) {
+            (Some((_, path)), _) => Ok(path),
+            (None, Some(path)) => Ok(path),
+            (None, None) => eyre::bail!("No APK was built for `{}`", arch.android_abi()),
+        }
+    }
+}
+
+/// What goes into every APK besides the libraries.
+struct ApkContents<'a> {
+    manifest: &'a apk::AndroidManifest,
+    icon_path: Option<&'a Utf8Path>,
+    sdk_path: &'a Path,
+    dex_path: &'a Path,
+    extra_dex: &'a [Utf8PathBuf],
+    compress_native_libs: bool,
+    page_size: u16,
+}
+
+/// Write and sign the APK at `apk_path` with `contents` and `libs`.
+fn write_apk(
+    contents: &ApkContents,
+    apk_path: &Path,
+    libs: &[(apk::Target, Utf8PathBuf)],
+    signer: &apk::Signer,
+) -> eyre::Result<()> {
+    status!(
+        "  {} building APK `{}`",
+        "Build".green().bold(),
+        apk_path.display()
+    );
+
+    let mut apk = apk::Apk::new(apk_path.to_path_buf(), contents.manifest.clone(), true)
+        .map_err(|e| eyre::eyre!("{}", e))?;
+
+    if let Some(icon_path) = contents.icon_path {
+        status!(
+            "   {} adding icon `{}`",
+            "Building".green().bold(),
+            icon_path
+        );
+    }
+
+    let start = Instant::now();
+    tracing::debug!(icon = ?contents.icon_path, sdk = %contents.sdk_path.display(), "adding resources");
+    apk.add_res(contents.icon_path.map(AsRef::as_ref), contents.sdk_path)
+        .map_err(|e| eyre::eyre!("{}", e))?;
+    timings::record("add res", start);
+
+    let start = Instant::now();
+    tracing::debug!(path = %contents.dex_path.display(), "adding dex");
+    apk.add_dex(contents.dex_path)
+        .map_err(|e| eyre::eyre!("{}", e))?;
+
+    for (i, extra_dex) in contents.extra_dex.iter().enumerate() {
+        let name = format!("classes{}.dex", i + 2);
+
+        status!(
+            "   {} adding dex `{}` as `{}`",
+            "Building".green().bold(),
+            extra_dex,
+            name
+        );
+
+        apk.add_file(
+            extra_dex.as_ref(),
+            Path::new(&name),
+            ZipFileOptions::Compressed,
+        )
+        .map_err(|e| eyre::eyre!("{}", e))?;
+    }
+
+    timings::record("add dex", start);
+
+    let start = Instant::now();
+
+    for (apk_target, lib_path) in libs {
+        status!(
+            "   {} adding library `{}`",
+            "Building".green().bold(),
+            lib_path
+        );
+
+        tracing::debug!(abi = apk_target.android_abi(), path = %lib_path, "adding library");
+
+        add_lib(
+            &mut apk,
+            *apk_target,
+            lib_path.as_ref(),
+            contents.compress_native_libs,
+            contents.page_size,
+        )?;
+    }
+
+    timings::record("add lib", start);
+
+    let start = Instant::now();
+    apk.finish(Some(signer.clone()))
+        .map_err(|e| eyre::eyre!("{}", e))?;
+    timings::record("sign", start);
+
+    Ok(())
+}
+
+/// A record of what `build_apk` produced, written next to the APKs as
+/// `target/apk/<package>.build.json`.
+#[derive(Serialize)]
+struct BuildSummary<'a> {
+    package: &'a str,
+    targets: &'a [String],
+    libs: Vec<SummaryLib>,
+    sdk_version: u32,
+    ndk_version: Option<&'a str>,
+    signer_fingerprint: &'a str,
+    manifest: SummaryManifest<'a>,
+    apks: Vec<SummaryApk<'a>>,
+}
+
+#[derive(Serialize)]
+struct SummaryLib {
+    abi: &'static str,
+    path: Utf8PathBuf,
+    sha256: String,
+}
+
+#[derive(Serialize)]
+struct SummaryManifest<'a> {
+    package: Option<&'a str>,
+    version_code: Option<u32>,
+    version_name: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct SummaryApk<'a> {
+    /// The target of a split APK, `None` for the universal one.
+    abi: Option<&'static str>,
+    path: &'a Path,
+    sha256: String,
+}
+
+fn write_build_summary(target_dir: &Utf8Path, summary: &BuildSummary) -> eyre::Result<()> {
+    let apk_dir = target_dir.join("apk");
+    fs::create_dir_all(&apk_dir)?;
+
+    let path = apk_dir.join(format!("{}.build.json", summary.package));
+    tracing::debug!(%path, "writing build summary");
+
+    let json = serde_json::to_string_pretty(summary)?;
+    fs::write(&path, json + "\n").wrap_err_with(|| format!("Failed to write `{}`", path))?;
+
+    Ok(())
+}
+
+/// Install the rust targets, or cross, and download the NDK if configured,
+/// returning its path.
+fn prepare_toolchain(
+    project: &Project,
+    options: &BuildOptions,
+    global: &GlobalOptions,
+) -> eyre::Result<Option<PathBuf>> {
+    if options.no_cross {
+        for target in options.target.iter() {
+            crate::ensure_rust_target_installed(global, target)?;
+        }
+    } else {
+        crate::ensure_cross_installed(global)?;
+    }
+
+    match project.apk_metadata.ndk_version {
+        Some(ref version) => {
+            let start = Instant::now();
+            let ndk_path = download_android_ndk(
+                &project.metadata.target_directory,
+                version,
+                options.is_offline(),
+            )?;
+            timings::record("ndk", start);
+
+            Ok(Some(ndk_path))
+        }
+        None => Ok(None),
+    }
+}
+
+fn export_gradle(options: &ExportGradleOptions, global: &GlobalOptions) -> eyre::Result<()> {
+    let mut build = options.build.clone();
+    let project = Project::load(&mut build)?;
+    build.default_targets();
+
+    if build.target.is_empty() {
+        eyre::bail!(
+            "Target not specified, use `--target` or set `build.target` in `.cargo/config.toml`"
+        );
+    }
+
+    check_targets(&build.target)?;
+
+    // only the sources of the bundled activity can be exported
+    if project.classes_dex_path(&build)?.is_some() || !project.apk_metadata.dex.is_empty() {
+        eyre::bail!("Projects with custom dex files can't be exported, their sources are unknown");
+    }
+
+    let icon_path = project.icon_path()?;
+
+    if global.dry_run {
+        for target in build.target.iter() {
+            status!("  {} build `{}`", "Plan".green().bold(), target);
+        }
+
+        status!(
+            "  {} export Gradle project to `{}`",
+            "Plan".green().bold(),
+            options.out.display()
+        );
+
+        return Ok(());
+    }
+
+    let ndk_path = prepare_toolchain(&project, &build, global)?;
+    let libs = build_libs(
+        &project.metadata,
+        &project.package,
+        &build,
+        ndk_path.as_deref(),
+    )?;
+
+    let libs: Vec<_> = libs
+        .iter()
+        .map(|(target, lib_path)| (*target, lib_path.as_path()))
+        .collect();
+
+    gradle::export(
+        &options.out,
+        &project.package.name,
+        &project.manifest,
+        &libs,
+        icon_path.as_deref(),
+    )?;
+
+    status!(
+        "    {} Gradle project `{}`, a snapshot not updated by later builds",
+        "Exported".green().bold(),
+        options.out.display()
+    );
+
+    Ok(())
+}
+
+/// Print what `build_apk` would do, without running or writing anything,
+/// returning the path the APK would be written to.
+fn print_build_plan(
+    project: &Project,
+    options: &BuildOptions,
+    icon_path: Option<&Utf8Path>,
+    classes_dex: Option<&Path>,
+    extra_dex: &[Utf8PathBuf],
+    pem_path: Option<&Path>,
+) -> eyre::Result<BuiltApks> {
+    let Project {
+        metadata,
+        package,
+        apk_metadata,
+        manifest,
+        ..
+    } = project;
+
+    let target_directory = &metadata.target_directory;
+
+    let ndk_path = match apk_metadata.ndk_version {
+        Some(ref version) => {
+            let ndk_path = android_ndk_path(target_directory, version);
+
+            if ndk_path.join("source.properties").exists() {
+                status!("  {} use NDK `{}`", "Plan".green().bold(), ndk_path);
+            } else {
+                status!("  {} download NDK {}", "Plan".green().bold(), version);
+            }
+
+            Some(ndk_path)
+        }
+        None => None,
+    };
+
+    let mut lib_paths = Vec::new();
+
+    for target in options.target.iter() {
+        android_target(target)?;
+
+        let command = build_command(
+            package,
+            target,
+            options,
+            ndk_path.as_ref().map(AsRef::as_ref),
+        );
+
+        status!(
+            "  {} run `{}`",
+            "Plan".green().bold(),
+            display_command(&command)
+        );
+
+        lib_paths.push(expected_lib_path(metadata, package, target, options)?);
+    }
+
+    let sdk_path = android_sdk_path(target_directory, SDK_VERSION);
+
+    if sdk_path.exists() {
+        status!("  {} use SDK `{}`", "Plan".green().bold(), sdk_path);
+    } else {
+        status!(
+            "  {} download SDK android-{}",
+            "Plan".green().bold(),
+            SDK_VERSION
+        );
+    }
+
+    let lib_paths: Vec<&Utf8Path> = lib_paths.iter().map(Utf8PathBuf::as_path).collect();
+    let apk_path = universal_apk_path(target_directory, &package.name, &lib_paths);
+
+    let mut built = BuiltApks::default();
+
+    if !options.no_universal {
+        built.universal = Some(apk_path.into());
+    }
+
+    if options.split_abi {
+        for target in options.target.iter() {
+            let apk_target = android_target(target)?;
+            let split_path = target_directory.join("apk").join(format!(
+                "{}-{}.apk",
+                package.name,
+                apk_target.android_abi()
+            ));
+
+            built.splits.push((apk_target, split_path.into()));
+        }
+    }
+
+    if classes_dex.is_none() {
+        let dex_path = sdk_path
+            .parent()
+            .expect("sdk_path has parent")
+            .join("classes.dex");
+
+        status!("  {} write `{}`", "Plan".green().bold(), dex_path);
+    }
+
+    for (_, path) in built.iter() {
+        status!(
+            "  {} create APK `{}`",
+            "Plan".green().bold(),
+            path.display()
+        );
+    }
+
+    status!(
+        "  {} add manifest for `{}`",
+        "Plan".green().bold(),
+        manifest.package.as_deref().unwrap_or_default()
+    );
+
+    if let Some(icon_path) = icon_path {
+        status!("  {} add icon `{}`", "Plan".green().bold(), icon_path);
+    }
+
+    match classes_dex {
+        Some(dex) => status!(
+            "  {} add `{}` as `classes.dex`",
+            "Plan".green().bold(),
+            dex.display()
+        ),
+        None => status!("  {} add `classes.dex`", "Plan".green().bold()),
+    }
+
+    for (i, extra_dex) in extra_dex.iter().enumerate() {
+        status!(
+            "  {} add `{}` as `classes{}.dex`",
+            "Plan".green().bold(),
+            extra_dex,
+            i + 2
+        );
+    }
+
+    let strip = options.strips();
+
+    for lib_path in lib_paths.iter() {
+        let stripped = if strip { " (stripped)" } else { "" };
+        status!(
+            "  {} add library `{}`{}",
+            "Plan".green().bold(),
+            lib_path,
+            stripped
+        );
+    }
+
+    if options.symbols {
+        status!(
+            "  {} write unstripped libraries to `{}`",
+            "Plan".green().bold(),
+            symbols_path(target_directory, &package.name)
+        );
+    }
+
+    match pem_path {
+        Some(pem) => status!("  {} sign with `{}`", "Plan".green().bold(), pem.display()),
+        None => status!("  {} sign with the debug key", "Plan".green().bold()),
+    }
+
+    Ok(built)
+}
+
+/// Where cargo writes the cdylib for `target`.
+fn expected_lib_path(
+    metadata: &cargo_metadata::Metadata,
+    package: &cargo_metadata::Package,
+    target: &str,
+    options: &BuildOptions,
+) -> eyre::Result<Utf8PathBuf> {
+    let profile = match options.profile.as_deref() {
+        Some("dev") | None if !options.release => "debug",
+        Some(profile) => profile,
+        None => "release",
+    };
+
+    let mut lib_dir = metadata.target_directory.join(target).join(profile);
+
+    if options.example.is_some() {
+        lib_dir = lib_dir.join("examples");
+    }
+
+    Ok(lib_dir.join(format!("lib{}.so", options.lib_name(package)?)))
+}
+
+/// The SHA-256 fingerprint of a signing certificate, as hex.
+fn certificate_fingerprint(cert: &apk::Certificate) -> eyre::Result<String> {
+    let der = rasn::der::encode(cert).map_err(|e| eyre::eyre!("{}", e))?;
+    let digest = Sha256::digest(der);
+
+    Ok(digest.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// The SHA-256 of the file at `path`, as hex.
+fn file_sha256(path: &Path) -> eyre::Result<String> {
+    let mut file =
+        fs::File::open(path).wrap_err_with(|| format!("Failed to open `{}`", path.display()))?;
+
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+fn verify_sha256(path: &Path, expected: &str) -> eyre::Result<()> {
+    let actual = file_sha256(path)?;
+
+    if !actual.eq_ignore_ascii_case(expected.trim()) {
+        eyre::bail!(
+            "SHA-256 of `{}` is {}, expected {}",
+            path.display(),
+            actual,
+            expected
+        );
+    }
+
+    Ok(())
+}
+
+/// Add the library at `lib_path`, uncompressed libraries are aligned to
+/// `page_size`.
+fn add_lib(
+    apk: &mut apk::Apk,
+    target: apk::Target,
+    lib_path: &Path,
+    compress: bool,
+    page_size: u16,
+) -> eyre::Result<()> {
+    let file_name = lib_path.file_name().expect("lib_path has file name");
+    let dest = Path::new("lib").join(target.android_abi()).join(file_name);
+
+    let options = if compress {
+        ZipFileOptions::Compressed
+    } else {
+        ZipFileOptions::Aligned(page_size)
+    };
+
+    apk.add_file(lib_path, &dest, options)
+        .map_err(|e| eyre::eyre!("{}", e))
+}
+
+fn android_target(target: &str) -> eyre::Result<apk::Target> {
+    match target {
+        "aarch64-linux-android" => Ok(apk::Target::Arm64V8a),
+        "armv7-linux-androideabi" => Ok(apk::Target::ArmV7a),
+        "x86_64-linux-android" => Ok(apk::Target::X86_64),
+        "i686-linux-android" => Ok(apk::Target::X86),
+        _ => eyre::bail!("Target '{}' is not supported for android", target),
+    }
+}
+
+/// Check that every target is supported and builds a different ABI, the
+/// library of a later target would replace the earlier one in the APK.
+fn check_targets(targets: &[String]) -> eyre::Result<()> {
+    let mut abis = BTreeMap::new();
+
+    for target in targets {
+        let abi = android_target(target)?.android_abi();
+
+        match abis.insert(abi, target) {
+            Some(other) if other == target => {
+                eyre::bail!("Target `{}` is specified twice", target)
+            }
+            Some(other) => eyre::bail!(
+                "Targets `{}` and `{}` both build ABI `{}`",
+                other,
+                target,
+                abi
+            ),
+            None => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Build the library for every target, up to `--build-jobs` at a time,
+/// returning the cdylibs in the order of the targets.
+fn build_libs(
+    metadata: &cargo_metadata::Metadata,
+    package: &cargo_metadata::Package,
+    options: &BuildOptions,
+    ndk_path: Option<&Path>,
+) -> eyre::Result<Vec<(apk::Target, Utf8PathBuf)>> {
+    let mut targets = Vec::new();
+
+    for target in options.target.iter() {
+        targets.push((target.as_str(), android_target(target)?));
+    }
+
+    let concurrent = options.build_jobs.min(targets.len()).max(1);
+
+    // share the CPUs between concurrent builds, instead of each using all of them
+    let mut options = options.clone();
+
+    if concurrent > 1 && options.jobs.is_none() {
+        let cpus = thread::available_parallelism().map_or(1, |cpus| cpus.get());
+        options.jobs = Some((cpus / concurrent).max(1));
+    }
+
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let results = Mutex::new(Vec::new());
+
+    thread::scope(|scope| {
+        for _ in 0..concurrent {
+            scope.spawn(|| {
+                // stop picking up targets once one failed, running builds finish
+                while options.keep_going || !failed.load(Ordering::Relaxed) {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+
+                    let (target, apk_target) = match targets.get(index) {
+                        Some(&target) => target,
+                        None => break,
+                    };
+
+                    let mut options = options.clone();
+
+                    if concurrent > 1 {
+                        let target_dir = jobs_target_dir(&metadata.target_directory, target);
+                        options.target_dir = Some(target_dir.into());
+                    }
+
+                    let start = Instant::now();
+                    let result = build_lib(package, target, &options, ndk_path, concurrent > 1)
+                        .and_then(|artifact| artifact_cdylib(&artifact, &metadata.workspace_root));
+                    timings::record(format!("build {}", target), start);
+
+                    if result.is_err() {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+
+                    let mut results = results.lock().unwrap_or_else(|err| err.into_inner());
+                    results.push((index, apk_target, result));
+                }
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap_or_else(|err| err.into_inner());
+    results.sort_by_key(|(index, _, _)| *index);
+
+    if !options.keep_going || targets.len() == 1 {
+        let mut libs = Vec::new();
+
+        for (_, apk_target, result) in results {
+            libs.push((apk_target, result?));
+        }
+
+        return Ok(libs);
+    }
+
+    let mut libs = Vec::new();
+    let mut failed = 0;
+
+    for (index, apk_target, result) in results {
+        let target = targets[index].0;
+
+        match result {
+            Ok(lib_path) => {
+                status!("     {} `{}`", "Success".green().bold(), target);
+                libs.push((apk_target, lib_path));
+            }
+            Err(err) => {
+                status!("      {} `{}`: {}", "Failed".red().bold(), target, err);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        eyre::bail!("Build failed for {} of {} targets", failed, targets.len());
+    }
+
+    Ok(libs)
+}
+
+/// The target directory of `target` for concurrent builds.
+fn jobs_target_dir(target_directory: &Utf8Path, target: &str) -> Utf8PathBuf {
+    target_directory.join("apk").join("jobs").join(target)
+}
+
+/// Build the library for `target`, prefixing its output with the target when
+/// `prefixed`, so concurrent builds stay readable.
+fn build_lib(
+    package: &cargo_metadata::Package,
+    target: &str,
+    options: &BuildOptions,
+    ndk_path: Option<&Path>,
+    prefixed: bool,
+) -> eyre::Result<cargo_metadata::Artifact> {
+    let _span = tracing::info_span!("build", target).entered();
+
+    crate::emit(&crate::Message::BuildStarted {
+        package: &package.name,
+        target,
+    });
+
+    status!(
+        "  {} building library for target `{}`",
+        "Build".green().bold(),
+        target
+    );
+
+    let mut command = build_command(package, target, options, ndk_path);
+    let program = command.get_program().to_string_lossy().into_owned();
+
+    echo_command(&command, Level::DEBUG);
+
+    if prefixed {
+        command.stderr(process::Stdio::piped());
+    }
+
+    let mut process = command
+        .stdout(process::Stdio::piped())
+        .spawn()
+        .wrap_err_with(|| format!("Failed to run {}", program))?;
+
+    let stderr = process.stderr.take().map(|stderr| {
+        let target = String::from(target);
+
+        thread::spawn(move || {
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                eprintln!("[{}] {}", target, line);
+            }
+        })
+    });
+
+    let print = |text: &str| {
+        for line in text.lines() {
+            if prefixed {
+                status!("[{}] {}", target, line);
+            } else {
+                status!("{}", line);
+            }
+        }
+    };
+
+    let reader = BufReader::new(process.stdout.take().expect("stdout available"));
+
+    let mut package_artifact = None;
+
+    for message in cargo_metadata::Message::parse_stream(reader) {
+        match message? {
+            cargo_metadata::Message::CompilerArtifact(artifact)
+                if artifact.package_id == package.id && options.selects(&artifact.target) =>
+            {
+                package_artifact = Some(artifact);
+            }
+            cargo_metadata::Message::CompilerMessage(message) => {
+                print(&message.message.to_string());
+            }
+            cargo_metadata::Message::BuildScriptExecuted(_) => {}
+            cargo_metadata::Message::BuildFinished(_) => {}
+            cargo_metadata::Message::TextLine(line) => {
+                print(&line);
+            }
+            _ => {}
+        }
+    }
+
+    process.wait()?;
+
+    if let Some(stderr) = stderr {
+        let _ = stderr.join();
+    }
+
+    package_artifact.ok_or_else(|| eyre::eyre!("Artifact not generated"))
+}
+
+/// The `cross` or `cargo` command building the library for `target`.
+fn build_command(
+    package: &cargo_metadata::Package,
+    target: &str,
+    options: &BuildOptions,
+    ndk_path: Option<&Path>,
+) -> process::Command {
+    let program = if options.no_cross { "cargo" } else { "cross" };
+    let mut command = process::Command::new(program);
+
+    command
+        .arg("--color")
+        .arg(crate::cargo_color())
+        .arg("build")
+        .arg("--target")
+        .arg(target)
+        .arg("--message-format=json")
+        .arg("--package")
+        .arg(&package.name);
+
+    if let Some(ref example) = options.example {
+        command.arg("--example").arg(example);
+    } else {
+        command.arg("--lib");
+    }
+
+    if let Some(ref profile) = options.profile {
+        command.arg("--profile").arg(profile);
+    } else if options.release {
+        command.arg("--release");
+    }
+
+    if let Some(ref manifest_path) = options.manifest_path {
+        command.arg("--manifest-path").arg(manifest_path);
+    }
+
+    if let Some(ref target_dir) = options.target_dir {
+        command.arg("--target-dir").arg(target_dir);
+    }
+
+    command.args(options.cargo_flags());
+
+    if let Some(jobs) = options.jobs {
+        command.arg("--jobs").arg(jobs.to_string());
+    }
+
+    if !options.features.is_empty() {
+        command.arg("--features");
+        command.arg(options.features.join(","));
+    }
+
+    if options.no_default_features {
+        command.arg("--no-default-features");
+    }
+
+    if options.all_features {
+        command.arg("--all-features");
+    }
+
+    // a host path doesn't exist inside the cross container
+    if let Some(ndk_path) = ndk_path.filter(|_| options.no_cross) {
+        command.env("ANDROID_NDK_HOME", ndk_path);
+    }
+
+    match rustflags(target, options) {
+        Some(Rustflags::Config(config)) => {
+            command.arg("--config").arg(config);
+        }
+        Some(Rustflags::Encoded(flags)) => {
+            command.env_remove("RUSTFLAGS");
+            command.env("CARGO_ENCODED_RUSTFLAGS", flags);
+        }
+        None => {}
+    }
+
+    // without the flag cross picks up `CROSS_CONTAINER_ENGINE` from our environment
+    if let Some(engine) = options.container_engine {
+        command.env("CROSS_CONTAINER_ENGINE", engine.as_str());
+    }
+
+    command
+}
+
+/// How the extra rustflags of a target build are passed to cargo.
+enum Rustflags {
+    /// A `--config` override of `target.<triple>.rustflags`, which cargo joins
+    /// with the configured `rustflags` of the target.
+    Config(String),
+
+    /// The flags of `RUSTFLAGS` or `CARGO_ENCODED_RUSTFLAGS` with the extra
+    /// ones appended, as the configured `rustflags` are ignored once either
+    /// is set.
+    Encoded(String),
+}
+
+/// `--rustflags` and the metadata `rustflags` of `target`, kept separate
+/// from the flags cargo would use. `None` without any extra flags.
+fn rustflags(target: &str, options: &BuildOptions) -> Option<Rustflags> {
+    // like `RUSTFLAGS`, so `--rustflags "-C opt-level=2"` works
+    let cli = options
+        .rustflags
+        .iter()
+        .flat_map(|flags| flags.split_whitespace());
+    let metadata = options.target_rustflags.get(target).into_iter().flatten();
+
+    let mut extra: Vec<&str> = cli.chain(metadata.map(String::as_str)).collect();
+
+    // the load segments of the library must be aligned to the page size too
+    if options.page_size() == 16384 {
+        extra.extend(["-C", "link-arg=-Wl,-z,max-page-size=16384"]);
+    }
+
+    if extra.is_empty() {
+        return None;
+    }
+
+    let env_flags: Option<Vec<String>> = match env::var("CARGO_ENCODED_RUSTFLAGS") {
+        Ok(encoded) if !encoded.is_empty() => {
+            Some(encoded.split('\x1f').map(String::from).collect())
+        }
+        Ok(_) => Some(Vec::new()),
+        Err(_) => env::var("RUSTFLAGS")
+            .ok()
+            .map(|flags| flags.split_whitespace().map(String::from).collect()),
+    };
+
+    if let Some(mut flags) = env_flags {
+        flags.extend(extra.into_iter().map(String::from));
+        return Some(Rustflags::Encoded(flags.join("\x1f")));
+    }
+
+    // `target.<triple>.rustflags` replace `build.rustflags`, which are kept by
+    // adding them to the override
+    let mut flags = cargo_config::build_rustflags(&options.config, target);
+    flags.extend(extra.into_iter().map(String::from));
+
+    let flags = toml::Value::Array(flags.into_iter().map(toml::Value::String).collect());
+
+    Some(Rustflags::Config(format!(
+        "target.'{}'.rustflags={}",
+        target, flags
+    )))
+}
+
+/// Strip debug symbols from a copy of `lib_path`, returning the path of the
+/// copy, or `lib_path` itself if no strip tool is available.
+fn strip_lib(
+    lib_path: &Utf8Path,
+    ndk_path: Option<&Path>,
+    verbose: bool,
+) -> eyre::Result<Utf8PathBuf> {
+    let strip = ndk_path
+        .map(|ndk| ndk_llvm_tool(ndk, "llvm-strip"))
+        .filter(|strip| strip.exists())
+        .unwrap_or_else(|| PathBuf::from("llvm-strip"));
+
+    let file_name = lib_path.file_name().expect("lib_path has file name");
+    let lib_parent = lib_path.parent().expect("lib_path has parent");
+    let stripped_path = lib_parent.join("stripped").join(file_name);
+
+    fs::create_dir_all(lib_parent.join("stripped"))?;
+
+    let mut command = process::Command::new(&strip);
+
+    command
+        .arg("--strip-debug")
+        .arg(lib_path)
+        .arg("-o")
+        .arg(&stripped_path);
+
+    let output = command_output(&mut command, Level::DEBUG);
+
+    match output {
+        Ok(output) if output.status.success() => {}
+        _ => {
+            status!(
+                "  {} `{}` is not available, library will not be stripped",
+                "Warning".yellow().bold(),
+                strip.display(),
+            );
+
+            return Ok(lib_path.to_owned());
+        }
+    }
+
+    if verbose {
+        status!(
+            "   {} stripped library from {} to {} bytes",
+            "Building".green().bold(),
+            fs::metadata(lib_path)?.len(),
+            fs::metadata(&stripped_path)?.len(),
+        );
+    }
+
+    Ok(stripped_path)
+}
+
+fn ndk_llvm_tool(ndk_path: &Path, tool: &str) -> PathBuf {
+    let host = if cfg!(target_os = "windows") {
+        "windows-x86_64"
+    } else if cfg!(target_os = "macos") {
+        "darwin-x86_64"
+    } else {
+        "linux-x86_64"
+    };
+
+    ndk_path
+        .join("toolchains")
+        .join("llvm")
+        .join("prebuilt")
+        .join(host)
+        .join("bin")
+        .join(format!("{}{}", tool, std::env::consts::EXE_SUFFIX))
+}
+
+fn artifact_cdylib(
+    artifact: &cargo_metadata::Artifact,
+    workspace_root: &Utf8Path,
+) -> eyre::Result<Utf8PathBuf> {
+    let index = artifact
+        .target
+        .crate_types
+        .iter()
+        .position(|t| t == "cdylib")
+        .ok_or_else(|| eyre::eyre!("Target `{}` is not a cdylib", artifact.target.name))?;
+
+    let path = &artifact.filenames[index];
+
+    if path.exists() {
+        return Ok(path.clone());
+    }
+
+    // older versions of cross report paths inside the container, where the
+    // workspace is mounted at the root
+    match path.strip_prefix("/") {
+        Ok(relative) if workspace_root.join(relative).exists() => Ok(workspace_root.join(relative)),
+        _ => eyre::bail!("Built cdylib `{}` not found", path),
+    }
+}
+
+/// Everything the manifest is generated from, resolved from the package.
+struct ManifestConfig<'a> {
+    /// The crate name, the default package id and label are derived from it.
+    crate_name: &'a str,
+
+    /// The crate version, the default version name.
+    crate_version: String,
+
+    /// The directory of `Cargo.toml`, which metadata paths are relative to.
+    package_root: &'a Utf8Path,
+
+    /// The name of the library the activity loads.
+    lib_name: &'a str,
+
+    debuggable: bool,
+    ori_metadata: &'a OriMetadata,
+    apk_metadata: &'a Metadata,
+}
+
+impl<'a> ManifestConfig<'a> {
+    fn new(
+        package: &'a cargo_metadata::Package,
+        lib_name: &'a str,
+        debuggable: bool,
+        ori_metadata: &'a OriMetadata,
+        apk_metadata: &'a Metadata,
+    ) -> Self {
+        let package_root = package
+            .manifest_path
+            .parent()
+            .expect("manifest_path has parent");
+
+        Self {
+            crate_name: &package.name,
+            crate_version: package.version.to_string(),
+            package_root,
+            lib_name,
+            debuggable,
+            ori_metadata,
+            apk_metadata,
+        }
+    }
+}
+
+fn apk_manifest(config: &ManifestConfig) -> eyre::Result<apk::AndroidManifest> {
+    let ManifestConfig {
+        crate_name,
+        ref crate_version,
+        package_root,
+        lib_name,
+        debuggable,
+        ori_metadata,
+        apk_metadata,
+    } = *config;
+
+    let mut manifest = apk::AndroidManifest::default();
+
+    manifest.compile_sdk_version = Some(SDK_VERSION);
+    manifest.platform_build_version_code = Some(SDK_VERSION);
+    manifest.compile_sdk_version_codename = Some(SDK_RELEASE);
+    manifest.platform_build_version_name = Some(SDK_RELEASE);
+    manifest.sdk.target_sdk_version = Some(SDK_VERSION);
+    manifest.sdk.min_sdk_version = Some(MIN_SDK_VERSION);
+
+    let application_id = apk_metadata
+        .application_id
+        .as_ref()
+        .or(apk_metadata.package.as_ref());
+
+    manifest.package = match application_id {
+        Some(application_id) => Some(application_id.clone()),
+        None => Some(default_package_id(crate_name)),
+    };
+
+    let lookup = |key: &str| apk_metadata.manifest_placeholders.get(key).cloned();
+
+    // only the application id ends up in the manifest, which is validated
+    // once complete
+    if let Some(ref package) = apk_metadata.package {
+        let package = placeholders::substitute(package, lookup)
+            .wrap_err("Failed to substitute manifest placeholders")?;
+        validate_package_id(&package)?;
+    }
+
+    // lets debug builds be installed next to release builds
+    let debug_suffix = match apk_metadata.debug_application_id_suffix {
+        Some(ref suffix) if debuggable => suffix.as_str(),
+        None if debuggable => DEBUG_APPLICATION_ID_SUFFIX,
+        _ => "",
+    };
+
+    if let Some(ref mut application_id) = manifest.package {
+        application_id.push_str(debug_suffix);
+    }
+
+    match apk_metadata.version_code {
+        Some(version_code) => manifest.version_code = Some(version_code),
+        None => manifest.version_code = Some(1),
+    }
+
+    match apk_metadata.version_name {
+        Some(ref version_name) => manifest.version_name = Some(version_name.clone()),
+        None => manifest.version_name = Some(crate_version.clone()),
+    }
+
+    // release builds keep the clean version name
+    if let Some(strategy) = apk_metadata.version_name_suffix.filter(|_| debuggable) {
+        let suffix = strategy.suffix(package_root.as_ref())?;
+
+        if let Some(ref mut version_name) = manifest.version_name {
+            *version_name = format!("{}-{}", version_name, suffix);
+        }
+    }
+
+    for feature in apk_metadata.uses_feature.iter() {
+        manifest.uses_feature.push(feature.to_manifest()?);
+    }
+
+    for permission in apk_metadata.uses_permission.iter() {
+        manifest.uses_permission.push(permission.to_manifest());
+    }
+
+    if apk_metadata.tv {
+        manifest.uses_feature.push(apk::manifest::Feature {
+            name: Some(String::from("android.software.leanback")),
+            required: Some(false),
+            version: None,
+            opengles_version: None,
+        });
+    }
+
+    let mut label = apk_metadata
+        .label
+        .clone()
+        .or_else(|| ori_metadata.name.clone())
+        .unwrap_or_else(|| String::from(crate_name));
+
+    if !debug_suffix.is_empty() {
+        label += " (debug)";
+    }
+
+    manifest.application.label = Some(label.clone());
+
+    if debuggable {
+        manifest.application.debuggable = Some(true);
+    }
+
+    for (name, value) in apk_metadata.application_meta_data.iter() {
+        if name.trim().is_empty() {
+            eyre::bail!("Application meta-data names must not be empty");
+        }
+
+        // references need `android:resource`, which the manifest doesn't have
+        if value.starts_with('@') {
+            eyre::bail!(
+                "Application meta-data `{}` is a resource reference `{}`, resources aren't supported, only string values",
+                name,
+                value
+            );
+        }
+
+        manifest
+            .application
+            .meta_data
+            .push(apk::manifest::MetaData {
+                name: name.clone(),
+                value: value.clone(),
+            });
+    }
+
+    manifest.application.theme = match apk_metadata.theme {
+        Some(ref theme) => Some(theme.clone()),
+        None => Some(String::from(DEFAULT_THEME)),
+    };
+
+    let mut activity = apk::manifest::Activity {
+        name: Some(
+            apk_metadata
+                .activity_name
+                .clone()
+                .unwrap_or_else(|| String::from(ORI_ACTIVITY)),
+        ),
+        exported: Some(true),
+        hardware_accelerated: Some(apk_metadata.hardware_accelerated.unwrap_or(true)),
+        meta_data: vec![apk::manifest::MetaData {
+            name: String::from("android.app.lib_name"),
+            value: String::from(lib_name),
+        }],
+        intent_filters: vec![apk::manifest::IntentFilter {
+            actions: vec![String::from("android.intent.action.MAIN")],
+            categories: vec![String::from("android.intent.category.LAUNCHER")],
+            ..Default::default()
+        }],
+        config_changes: Some(DEFAULT_CONFIG_CHANGES.join("|")),
+        launch_mode: Some(String::from("singleTop")),
+        window_soft_input_mode: Some(String::from("adjustResize")),
+        ..Default::default()
+    };
+
+    activity.label = Some(label);
+
+    if let Some(ref config_changes) = apk_metadata.config_changes {
+        // an empty list recreates the activity on every change
+        activity.config_changes = if config_changes.is_empty() {
+            None
+        } else {
+            Some(config_changes.join("|"))
+        };
+    }
+
+    if let Some(ref launch_mode) = apk_metadata.launch_mode {
+        activity.launch_mode = Some(launch_mode.clone());
+    }
+
+    if let Some(ref mode) = apk_metadata.window_soft_input_mode {
+        activity.window_soft_input_mode = Some(mode.clone());
+    }
+
+    if let Some(ref orientation) = apk_metadata.orientation {
+        activity.orientation = Some(orientation.clone());
+    }
+
+    if apk_metadata.tv {
+        activity.intent_filters[0]
+            .categories
+            .push(String::from("android.intent.category.LEANBACK_LAUNCHER"));
+    }
+
+    for filter in apk_metadata.intent_filters.iter() {
+        activity.intent_filters.push(filter.to_manifest()?);
+    }
+
+    let main_activity = activity.name.clone();
+    manifest.application.activities.push(activity);
+
+    for extra in apk_metadata.activities.iter() {
+        let duplicate = manifest
+            .application
+            .activities
+            .iter()
+            .any(|activity| activity.name.as_deref() == Some(extra.name.as_str()));
+
+        if duplicate {
+            eyre::bail!(
+                "Activity `{}` is declared twice, the main activity is `{}`",
+                extra.name,
+                main_activity.as_deref().unwrap_or_default()
+            );
+        }
+
+        manifest.application.activities.push(extra.to_manifest()?);
+    }
+
+    if let Some(ref overlay) = apk_metadata.manifest_overlay {
+        let overlay_path = package_root.join(overlay);
+        let xml = fs::read_to_string(&overlay_path)
+            .wrap_err_with(|| format!("Failed to read manifest overlay `{}`", overlay_path))?;
+
+        overlay::merge(&mut manifest, &xml)
+            .wrap_err_with(|| format!("Invalid manifest overlay `{}`", overlay_path))?;
+    }
+
+    placeholders::substitute_manifest(&mut manifest, lookup)
+        .wrap_err("Failed to substitute manifest placeholders")?;
+
+    // release builds must never be debuggable, not even by the overlay
+    if !debuggable {
+        manifest.application.debuggable = None;
+    }
+
+    validate_manifest(&manifest)?;
+
+    Ok(manifest)
+}
+
+/// Validate the values of the complete manifest, after the overlay is
+/// merged and placeholders are substituted.
+fn validate_manifest(manifest: &apk::AndroidManifest) -> eyre::Result<()> {
+    if let Some(ref package) = manifest.package {
+        validate_package_id(package)?;
+    }
+
+    if let Some(ref theme) = manifest.application.theme {
+        if !theme.starts_with("@android:style/") && !theme.starts_with("@style/") {
+            eyre::bail!(
+                "Theme `{}` must be a `@android:style/...` or `@style/...` reference",
+                theme
+            );
+        }
+    }
+
+    for activity in manifest.application.activities.iter() {
+        validate_activity(activity)?;
+    }
+
+    validate_exported(manifest)
+}
+
+fn validate_activity(activity: &apk::manifest::Activity) -> eyre::Result<()> {
+    if let Some(ref config_changes) = activity.config_changes {
+        for change in config_changes.split('|') {
+            if !CONFIG_CHANGES.contains(&change) {
+                eyre::bail!(
+                    "Unknown config change `{}`, expected one of {}",
+                    change,
+                    CONFIG_CHANGES.join(", ")
+                );
+            }
+        }
+    }
+
+    if let Some(ref launch_mode) = activity.launch_mode {
+        if !LAUNCH_MODES.contains(&launch_mode.as_str()) {
+            eyre::bail!(
+                "Unknown launch mode `{}`, expected one of {}",
+                launch_mode,
+                LAUNCH_MODES.join(", ")
+            );
+        }
+    }
+
+    if let Some(ref mode) = activity.window_soft_input_mode {
+        validate_soft_input_mode(mode)?;
+    }
+
+    if let Some(ref orientation) = activity.orientation {
+        if !SCREEN_ORIENTATIONS.contains(&orientation.as_str()) {
+            eyre::bail!(
+                "Unknown orientation `{}`, expected one of {}",
+                orientation,
+                SCREEN_ORIENTATIONS.join(", ")
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_soft_input_mode(mode: &str) -> eyre::Result<()> {
+    let flags: Vec<&str> = mode.split('|').map(str::trim).collect();
+
+    for flag in flags.iter() {
+        if !SOFT_INPUT_MODES.contains(flag) {
+            eyre::bail!(
+                "Unknown soft input mode `{}`, expected `|` separated flags of {}",
+                flag,
+                SOFT_INPUT_MODES.join(", ")
+            );
+        }
+    }
+
+    for prefix in ["state", "adjust"] {
+        let count = flags.iter().filter(|flag| flag.starts_with(prefix)).count();
+
+        if count > 1 {
+            eyre::bail!(
+                "Soft input mode `{}` has more than one `{}...` flag",
+                mode,
+                prefix
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// API 31 requires every component with an intent filter to declare
+/// `android:exported`, components from the manifest overlay set it there.
+fn validate_exported(manifest: &apk::AndroidManifest) -> eyre::Result<()> {
+    for activity in manifest.application.activities.iter() {
+        if !activity.intent_filters.is_empty() && activity.exported.is_none() {
+            eyre::bail!(
+                "Activity `{}` has an intent filter but no explicit `android:exported`",
+                activity.name.as_deref().unwrap_or("<unnamed>")
+            );
+        }
+    }
+
+    Ok(())
+}
+
+const JAVA_KEYWORDS: &[&str] = &[
+    "abstract",
+    "assert",
+    "boolean",
+    "break",
+    "byte",
+    "case",
+    "catch",
+    "char",
+    "class",
+    "const",
+    "continue",
+    "default",
+    "do",
+    "double",
+    "else",
+    "enum",
+    "extends",
+    "false",
+    "final",
+    "finally",
+    "float",
+    "for",
+    "goto",
+    "if",
+    "implements",
+    "import",
+    "instanceof",
+    "int",
+    "interface",
+    "long",
+    "native",
+    "new",
+    "null",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "return",
+    "short",
+    "static",
+    "strictfp",
+    "super",
+    "switch",
+    "synchronized",
+    "this",
+    "throw",
+    "throws",
+    "transient",
+    "true",
+    "try",
+    "void",
+    "volatile",
+    "while",
+];
+
+/// Derive an application id from a crate name, e.g. `my-app` becomes
+/// `com.example.my_app`.
+fn default_package_id(name: &str) -> String {
+    let mut segment: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    if !segment.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        segment.insert_str(0, "app_");
+    }
+
+    if JAVA_KEYWORDS.contains(&segment.as_str()) {
+        segment.push('_');
+    }
+
+    format!("com.example.{}", segment)
+}
+
+fn validate_package_id(id: &str) -> eyre::Result<()> {
+    let segments: Vec<&str> = id.split('.').collect();
+
+    if segments.len() < 2 {
+        eyre::bail!(
+            "Package `{}` must have at least two segments, e.g. `com.example.app`",
+            id
+        );
+    }
+
+    for segment in segments {
+        let valid = segment.starts_with(|c: char| c.is_ascii_alphabetic())
+            && segment
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+        if !valid {
+            eyre::bail!(
+                "Package `{}` has invalid segment `{}`, segments must start with a letter \
+                 and contain only letters, digits and underscores",
+                id,
+                segment
+            );
+        }
+
+        if JAVA_KEYWORDS.contains(&segment) {
+            eyre::bail!(
+                "Package `{}` has segment `{}`, which is a reserved Java keyword",
+                id,
+                segment
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn download_android_sdk(
+    target_directory: &Utf8Path,
+    version: u32,
+    offline: bool,
+) -> eyre::Result<PathBuf> {
+    let apk_dir = target_directory.join("apk");
+    let android = format!("android-{}", version);
+    let apk_path = android_sdk_path(target_directory, version);
+
+    if apk_path.exists() {
+        return Ok(apk_path.into());
+    }
+
+    if offline {
+        eyre::bail!(
+            "SDK platform `{}` is not downloaded and network access is disabled, run `cargo ori apk sdk` first",
+            android
+        );
+    }
+
+    status!("{} {}", "Downloading".green(), android.green());
+    tracing::debug!(%apk_dir, "sdkmanager `platforms;{}`", android);
+
+    download_package(
+        &apk_dir,
+        &format!("platforms;{}", android),
+        Some("android.jar"),
+        apk_path.parent().expect("apk_path has parent"),
+    )?;
+
+    // lets users pin the SDK with `--sdk-sha256`
+    status!(
+        "  {} `android.jar` sha256 {}",
+        "Downloaded".green().bold(),
+        file_sha256(apk_path.as_ref())?
+    );
+
+    Ok(apk_path.into())
+}
+
+fn download_android_ndk(
+    target_directory: &Utf8Path,
+    version: &str,
+    offline: bool,
+) -> eyre::Result<PathBuf> {
+    let apk_dir = target_directory.join("apk");
+    let ndk_path = android_ndk_path(target_directory, version);
+
+    if ndk_path.join("source.properties").exists() {
+        return Ok(ndk_path.into());
+    }
+
+    if offline {
+        eyre::bail!(
+            "NDK `{}` is not downloaded and network access is disabled, run `cargo ori apk sdk` first",
+            version
+        );
+    }
 
-fn artifact_cdylib(artifact: &cargo_metadata::Artifact) -> eyre::Result<&Utf8Path> {
-    let index = artifact
-        .target
-        .crate_types
-        .iter()
-        .position(|t| t == "cdylib")
-        .ok_or_else(|| eyre::eyre!("No cdylib built"))?;
+    status!(
+        "{} {}",
+        "Downloading".green(),
+        format!("ndk {}", version).green()
+    );
+    tracing::debug!(%apk_dir, "sdkmanager `ndk;{}`", version);
+
+    download_package(&apk_dir, &format!("ndk;{}", version), None, &ndk_path)?;
 
-    Ok(&artifact.filenames[index])
+    Ok(ndk_path.into())
 }
 
-fn apk_manifest(
-    package: &cargo_metadata::Package,
-    ori_metadata: &OriMetadata,
-    apk_metadata: &Metadata,
-) -> eyre::Result<apk::AndroidManifest> {
-    let mut manifest = apk::AndroidManifest::default();
+/// Attempts at downloading an SDK package before giving up.
+const DOWNLOAD_ATTEMPTS: u32 = 3;
 
-    let version = 34;
-    let version_code = 14;
-    let min_version = 21;
+/// Download and extract `package` into `apk_dir`, retrying with backoff and
+/// removing the partial extraction at `extract_dir` between attempts.
+fn download_package(
+    apk_dir: &Utf8Path,
+    package: &str,
+    only: Option<&str>,
+    extract_dir: &Utf8Path,
+) -> eyre::Result<()> {
+    let mut delay = Duration::from_secs(1);
 
-    manifest.compile_sdk_version = Some(version);
-    manifest.platform_build_version_code = Some(version);
-    manifest.compile_sdk_version_codename = Some(version_code);
-    manifest.platform_build_version_name = Some(version_code);
-    manifest.sdk.target_sdk_version = Some(version);
-    manifest.sdk.min_sdk_version = Some(min_version);
+    for attempt in 1..=DOWNLOAD_ATTEMPTS {
+        let mut command = process::Command::new(env::current_exe()?);
 
-    match apk_metadata.package {
-        Some(ref package) => manifest.package = Some(package.clone()),
-        None => manifest.package = Some(format!(".{}", package.name.replace("-", "_"))),
-    }
+        command
+            .arg("ori")
+            .arg("apk")
+            .arg("download-package")
+            .arg(apk_dir)
+            .arg(package);
 
-    match apk_metadata.version_code {
-        Some(version_code) => manifest.version_code = Some(version_code),
-        None => manifest.version_code = Some(1),
-    }
+        if let Some(only) = only {
+            command.arg("--only").arg(only);
+        }
 
-    match apk_metadata.version_name {
-        Some(ref version_name) => manifest.version_name = Some(version_name.clone()),
-        None => manifest.version_name = Some(package.version.to_string()),
-    }
+        // the SDK manager prints its progress to stdout, which is reserved for
+        // messages with `--message-format json`
+        if crate::is_json_output() {
+            command.stdout(io::stderr());
+        } else {
+            command.stdout(process::Stdio::inherit());
+        }
 
-    for feature in apk_metadata.uses_feature.iter() {
-        manifest.uses_feature.push(apk::manifest::Feature {
-            name: Some(feature.clone()),
-            required: None,
-            version: None,
-            opengles_version: None,
-        });
-    }
+        let output = command
+            .output()
+            .wrap_err("Failed to run the SDK download")?;
 
-    for permission in apk_metadata.uses_permission.iter() {
-        manifest.uses_permission.push(apk::manifest::Permission {
-            name: permission.clone(),
-            max_sdk_version: None,
-        });
-    }
+        if output.status.success() {
+            return Ok(());
+        }
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        let reason = match stderr.trim() {
+            "" => "unknown error",
+            reason => reason,
+        };
 
-    match ori_metadata.name {
-        Some(ref name) => manifest.application.label = Some(name.clone()),
-        None => manifest.application.label = Some(package.name.clone()),
+        if extract_dir.exists() {
+            fs::remove_dir_all(extract_dir)?;
+        }
+
+        if attempt == DOWNLOAD_ATTEMPTS {
+            eyre::bail!(
+                "Failed to download `{}` after {} attempts: {}",
+                package,
+                DOWNLOAD_ATTEMPTS,
+                reason
+            );
+        }
+
+        status!(
+            "  {} download of `{}` failed: {}, retrying in {}s",
+            "Warning".yellow().bold(),
+            package,
+            reason,
+            delay.as_secs()
+        );
+
+        thread::sleep(delay);
+        delay *= 2;
     }
 
-    manifest.application.theme = Some(String::from(
-        "@android:style/Theme.DeviceDefault.NoActionBar.TranslucentDecor",
-    ));
+    Ok(())
+}
 
-    let mut activity = apk::manifest::Activity {
-        name: Some(String::from("ori.oriactivity.OriActivity")),
-        exported: Some(true),
-        hardware_accelerated: Some(true),
-        meta_data: vec![apk::manifest::MetaData {
-            name: String::from("android.app.lib_name"),
-            value: package.name.replace("-", "_"),
-        }],
-        intent_filters: vec![apk::manifest::IntentFilter {
-            actions: vec![String::from("android.intent.action.MAIN")],
-            categories: vec![String::from("android.intent.category.LAUNCHER")],
-            ..Default::default()
-        }],
-        config_changes: Some(
-            [
-                "orientation",
-                "keyboardHidden",
-                "keyboard",
-                "screenSize",
-                "smallestScreenSize",
-                "locale",
-                "layoutDirection",
-                "fontScale",
-                "screenLayout",
-                "density",
-                "uiMode",
-            ]
-            .join("|"),
-        ),
-        launch_mode: Some(String::from("singleTop")),
-        window_soft_input_mode: Some(String::from("adjustResize")),
-        ..Default::default()
+/// Download a package in this process for [`download_package`], exiting with
+/// the reason on stderr if it fails.
+fn download_package_in_process(options: &DownloadPackageOptions) {
+    let host_os = match android_host_os() {
+        Ok(host_os) => host_os,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
     };
 
-    match ori_metadata.name {
-        Some(ref name) => activity.label = Some(name.clone()),
-        None => activity.label = Some(package.name.clone()),
-    }
+    // the match needs a `'static` name, the process ends after the download
+    let allow_list = options
+        .only
+        .clone()
+        .map(|name| [android_sdkmanager::MatchType::EntireName(name.leak())]);
 
-    manifest.application.activities.push(activity);
+    // the SDK manager panics on network errors, keep the panic quiet and
+    // report only its message
+    panic::set_hook(Box::new(|_| {}));
 
-    Ok(manifest)
-}
+    let result = panic::catch_unwind(|| {
+        android_sdkmanager::download_and_extract_packages(
+            options.install_dir.as_str(),
+            host_os,
+            &[options.package.as_str()],
+            allow_list.as_ref().map(|list| list.as_slice()),
+        )
+    });
 
-fn download_android_sdk(target_directory: &Utf8Path, version: u32) -> eyre::Result<PathBuf> {
-    let apk_dir = target_directory.join("apk");
-    let android = format!("android-{}", version);
-    let apk_path = apk_dir.join("platforms").join(&android).join("android.jar");
+    if let Err(payload) = result {
+        let reason = payload
+            .downcast_ref::<String>()
+            .map(String::as_str)
+            .or_else(|| payload.downcast_ref::<&str>().copied())
+            .unwrap_or("unknown error");
 
-    if apk_path.exists() {
-        return Ok(apk_path.into());
+        eprintln!("{}", reason);
+        process::exit(1);
     }
+}
 
-    println!("{} {}", "Downloading".green(), android.green());
-
-    android_sdkmanager::download_and_extract_packages(
-        apk_dir.as_str(),
-        android_host_os(),
-        &[&format!("platforms;{}", android)],
-        Some(&[android_sdkmanager::MatchType::EntireName("android.jar")]),
-    );
+/// The `android.jar` of the downloaded SDK platform.
+fn android_sdk_path(target_directory: &Utf8Path, version: u32) -> Utf8PathBuf {
+    target_directory
+        .join("apk")
+        .join("platforms")
+        .join(format!("android-{}", version))
+        .join("android.jar")
+}
 
-    Ok(apk_path.into())
+fn android_ndk_path(target_directory: &Utf8Path, version: &str) -> Utf8PathBuf {
+    target_directory.join("apk").join("ndk").join(version)
 }
 
 fn is_adb_installed() -> bool {
@@ -608,14 +4891,651 @@ fn ensure_adb_installed() -> eyre::Result<()> {
     Ok(())
 }
 
-fn android_host_os() -> android_sdkmanager::HostOs {
+fn android_host_os() -> eyre::Result<android_sdkmanager::HostOs> {
     if cfg!(target_os = "linux") {
-        android_sdkmanager::HostOs::Linux
+        Ok(android_sdkmanager::HostOs::Linux)
     } else if cfg!(target_os = "windows") {
-        android_sdkmanager::HostOs::Windows
+        Ok(android_sdkmanager::HostOs::Windows)
     } else if cfg!(target_os = "macos") {
-        android_sdkmanager::HostOs::MacOs
+        Ok(android_sdkmanager::HostOs::MacOs)
     } else {
-        panic!("Host os not supported")
+        eyre::bail!(
+            "Host OS `{}` is not supported for SDK download",
+            std::env::consts::OS
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn uses_feature_from_name() {
+        let feature: UsesFeature =
+            serde_json::from_value(json!("android.hardware.camera")).unwrap();
+        let feature = feature.to_manifest().unwrap();
+
+        assert_eq!(feature.name.as_deref(), Some("android.hardware.camera"));
+        assert_eq!(feature.required, None);
+        assert_eq!(feature.opengles_version, None);
+    }
+
+    #[test]
+    fn uses_feature_from_table() {
+        let feature: UsesFeature = serde_json::from_value(json!({
+            "name": "android.hardware.vulkan.level",
+            "required": false,
+            "version": 1,
+        }))
+        .unwrap();
+        let feature = feature.to_manifest().unwrap();
+
+        assert_eq!(
+            feature.name.as_deref(),
+            Some("android.hardware.vulkan.level")
+        );
+        assert_eq!(feature.required, Some(false));
+        assert_eq!(feature.version, Some(1));
+    }
+
+    #[test]
+    fn uses_feature_gles_version() {
+        let feature: UsesFeature =
+            serde_json::from_value(json!({ "gles-version": "3.1" })).unwrap();
+        assert_eq!(
+            feature.to_manifest().unwrap().opengles_version,
+            Some((3, 1))
+        );
+
+        let feature: UsesFeature = serde_json::from_value(json!({ "gles-version": "3" })).unwrap();
+        assert_eq!(
+            feature.to_manifest().unwrap().opengles_version,
+            Some((3, 0))
+        );
+
+        let feature: UsesFeature =
+            serde_json::from_value(json!({ "gles-version": "three" })).unwrap();
+        assert!(feature.to_manifest().is_err());
+    }
+
+    #[test]
+    fn uses_feature_rejects_unknown_keys() {
+        let feature =
+            serde_json::from_value::<UsesFeature>(json!({ "nmae": "android.hardware.camera" }));
+        assert!(feature.is_err());
+    }
+
+    #[test]
+    fn uses_permission_from_name() {
+        let permission: UsesPermission =
+            serde_json::from_value(json!("android.permission.CAMERA")).unwrap();
+        let permission = permission.to_manifest();
+
+        assert_eq!(permission.name, "android.permission.CAMERA");
+        assert_eq!(permission.max_sdk_version, None);
+    }
+
+    #[test]
+    fn uses_permission_from_table() {
+        let permission: UsesPermission = serde_json::from_value(json!({
+            "name": "android.permission.WRITE_EXTERNAL_STORAGE",
+            "max-sdk-version": 28,
+        }))
+        .unwrap();
+        let permission = permission.to_manifest();
+
+        assert_eq!(permission.name, "android.permission.WRITE_EXTERNAL_STORAGE");
+        assert_eq!(permission.max_sdk_version, Some(28));
+    }
+
+    #[test]
+    fn uses_permission_table_requires_name() {
+        let permission = serde_json::from_value::<UsesPermission>(json!({ "max-sdk-version": 28 }));
+        assert!(permission.is_err());
+    }
+
+    #[test]
+    fn install_failure_code_from_adb_output() {
+        let output = "Performing Streamed Install\nadb: failed to install app.apk: Failure \
+                      [INSTALL_FAILED_UPDATE_INCOMPATIBLE: Existing package signatures do not match]";
+
+        assert_eq!(
+            install_failure_code(output).as_deref(),
+            Some("INSTALL_FAILED_UPDATE_INCOMPATIBLE")
+        );
+    }
+
+    #[test]
+    fn install_failure_code_of_parse_failures() {
+        let output = "Failure [INSTALL_PARSE_FAILED_NO_CERTIFICATES: No signature found]";
+
+        assert_eq!(
+            install_failure_code(output).as_deref(),
+            Some("INSTALL_PARSE_FAILED_NO_CERTIFICATES")
+        );
+    }
+
+    #[test]
+    fn install_failure_code_of_success() {
+        assert_eq!(
+            install_failure_code("Performing Streamed Install\nSuccess\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn signature_mismatch_codes() {
+        assert!(is_signature_mismatch("INSTALL_FAILED_UPDATE_INCOMPATIBLE"));
+        assert!(is_signature_mismatch(
+            "INSTALL_FAILED_SHARED_USER_INCOMPATIBLE"
+        ));
+        assert!(!is_signature_mismatch("INSTALL_FAILED_VERSION_DOWNGRADE"));
+    }
+
+    #[test]
+    fn shell_quote_plain() {
+        assert_eq!(shell_quote("hello world"), "'hello world'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn shell_quote_single_quotes() {
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    fn shell_quote_keeps_shell_syntax_literal() {
+        assert_eq!(shell_quote("$HOME; rm -rf /"), "'$HOME; rm -rf /'");
+    }
+
+    #[test]
+    fn utc_timestamp_of_epoch() {
+        assert_eq!(utc_timestamp(0), "19700101.000000");
+    }
+
+    #[test]
+    fn utc_timestamp_of_time_of_day() {
+        assert_eq!(utc_timestamp(1_700_000_000), "20231114.221320");
+    }
+
+    #[test]
+    fn utc_timestamp_of_leap_days() {
+        assert_eq!(utc_timestamp(951_782_400), "20000229.000000");
+
+        // 2100 is not a leap year
+        assert_eq!(utc_timestamp(4_107_542_399), "21000228.235959");
+        assert_eq!(utc_timestamp(4_107_542_400), "21000301.000000");
+    }
+
+    #[test]
+    fn default_package_id_of_crate_names() {
+        assert_eq!(default_package_id("my-app"), "com.example.my_app");
+        assert_eq!(default_package_id("MyApp"), "com.example.myapp");
+        assert_eq!(default_package_id("2048"), "com.example.app_2048");
+        assert_eq!(default_package_id("_private"), "com.example.app__private");
+        assert_eq!(default_package_id("native"), "com.example.native_");
+    }
+
+    #[test]
+    fn default_package_id_is_valid() {
+        for name in ["my-app", "2048", "native", "ünïcode", "a.b"] {
+            let id = default_package_id(name);
+            assert!(validate_package_id(&id).is_ok(), "`{}` from `{}`", id, name);
+        }
+    }
+
+    #[test]
+    fn validate_package_id_accepts_valid_ids() {
+        assert!(validate_package_id("com.example.app").is_ok());
+        assert!(validate_package_id("org.ori_ui.demo2").is_ok());
+    }
+
+    #[test]
+    fn validate_package_id_rejects_invalid_ids() {
+        for id in [
+            "app",
+            "com..app",
+            "com.example.",
+            "com.1app",
+            "com.example.my-app",
+            "com.example.class",
+        ] {
+            assert!(validate_package_id(id).is_err(), "`{}`", id);
+        }
+    }
+
+    fn generate(
+        apk_metadata: serde_json::Value,
+        ori_metadata: serde_json::Value,
+        debuggable: bool,
+    ) -> eyre::Result<apk::AndroidManifest> {
+        generate_in(
+            Utf8Path::new("/nonexistent"),
+            apk_metadata,
+            ori_metadata,
+            debuggable,
+        )
+    }
+
+    fn generate_in(
+        package_root: &Utf8Path,
+        apk_metadata: serde_json::Value,
+        ori_metadata: serde_json::Value,
+        debuggable: bool,
+    ) -> eyre::Result<apk::AndroidManifest> {
+        let apk_metadata = serde_json::from_value(apk_metadata)?;
+        let ori_metadata = serde_json::from_value(ori_metadata)?;
+
+        apk_manifest(&ManifestConfig {
+            crate_name: "my-app",
+            crate_version: String::from("0.3.1"),
+            package_root,
+            lib_name: "my_app",
+            debuggable,
+            ori_metadata: &ori_metadata,
+            apk_metadata: &apk_metadata,
+        })
+    }
+
+    #[test]
+    fn manifest_defaults() {
+        let manifest = generate(json!({}), json!({}), false).unwrap();
+
+        assert_eq!(manifest.package.as_deref(), Some("com.example.my_app"));
+        assert_eq!(manifest.version_code, Some(1));
+        assert_eq!(manifest.version_name.as_deref(), Some("0.3.1"));
+        assert_eq!(manifest.application.label.as_deref(), Some("my-app"));
+        assert_eq!(manifest.application.theme.as_deref(), Some(DEFAULT_THEME));
+
+        let activity = &manifest.application.activities[0];
+        assert_eq!(activity.name.as_deref(), Some(ORI_ACTIVITY));
+        assert_eq!(activity.meta_data[0].value, "my_app");
+    }
+
+    #[test]
+    fn manifest_versions() {
+        let apk_metadata = json!({ "version-code": 42, "version-name": "1.0.0-beta" });
+        let manifest = generate(apk_metadata, json!({}), false).unwrap();
+
+        assert_eq!(manifest.version_code, Some(42));
+        assert_eq!(manifest.version_name.as_deref(), Some("1.0.0-beta"));
+    }
+
+    #[test]
+    fn manifest_application_id() {
+        let apk_metadata = json!({ "application-id": "org.ori.demo" });
+
+        let manifest = generate(apk_metadata.clone(), json!({}), false).unwrap();
+        assert_eq!(manifest.package.as_deref(), Some("org.ori.demo"));
+
+        let manifest = generate(apk_metadata, json!({}), true).unwrap();
+        assert_eq!(manifest.package.as_deref(), Some("org.ori.demo.debug"));
+
+        let apk_metadata = json!({ "application-id": "org.ori.class" });
+        assert!(generate(apk_metadata, json!({}), false).is_err());
+    }
+
+    #[test]
+    fn manifest_features_and_permissions() {
+        let apk_metadata = json!({
+            "uses-feature": [
+                "android.hardware.camera",
+                { "name": "android.hardware.vulkan.level", "version": 1 },
+            ],
+            "uses-permission": [
+                "android.permission.CAMERA",
+                { "name": "android.permission.WRITE_EXTERNAL_STORAGE", "max-sdk-version": 28 },
+            ],
+        });
+        let manifest = generate(apk_metadata, json!({}), false).unwrap();
+
+        let features: Vec<_> = manifest
+            .uses_feature
+            .iter()
+            .map(|feature| (feature.name.as_deref().unwrap(), feature.version))
+            .collect();
+        assert_eq!(
+            features,
+            [
+                ("android.hardware.camera", None),
+                ("android.hardware.vulkan.level", Some(1)),
+            ]
+        );
+
+        let permissions: Vec<_> = manifest
+            .uses_permission
+            .iter()
+            .map(|permission| (permission.name.as_str(), permission.max_sdk_version))
+            .collect();
+        assert_eq!(
+            permissions,
+            [
+                ("android.permission.CAMERA", None),
+                ("android.permission.WRITE_EXTERNAL_STORAGE", Some(28)),
+            ]
+        );
+    }
+
+    #[test]
+    fn manifest_label_from_ori_metadata() {
+        let manifest = generate(json!({}), json!({ "name": "My App" }), false).unwrap();
+        assert_eq!(manifest.application.label.as_deref(), Some("My App"));
+        assert_eq!(
+            manifest.application.activities[0].label.as_deref(),
+            Some("My App")
+        );
+    }
+
+    #[test]
+    fn manifest_label_from_apk_metadata() {
+        let apk_metadata = json!({ "label": "Demo" });
+        let manifest = generate(apk_metadata, json!({ "name": "My App" }), false).unwrap();
+        assert_eq!(manifest.application.label.as_deref(), Some("Demo"));
+    }
+
+    #[test]
+    fn manifest_label_of_debug_builds() {
+        let manifest = generate(json!({}), json!({ "name": "My App" }), true).unwrap();
+        assert_eq!(
+            manifest.application.label.as_deref(),
+            Some("My App (debug)")
+        );
+
+        // without a suffix both builds can't be installed at once, so they
+        // don't need telling apart
+        let apk_metadata = json!({ "debug-application-id-suffix": "" });
+        let manifest = generate(apk_metadata, json!({ "name": "My App" }), true).unwrap();
+        assert_eq!(manifest.application.label.as_deref(), Some("My App"));
+    }
+
+    #[test]
+    fn manifest_tv() {
+        let manifest = generate(json!({ "tv": true }), json!({}), false).unwrap();
+
+        let leanback = manifest
+            .uses_feature
+            .iter()
+            .find(|feature| feature.name.as_deref() == Some("android.software.leanback"))
+            .expect("the leanback feature");
+        assert_eq!(leanback.required, Some(false));
+        assert!(manifest.application.activities[0].intent_filters[0]
+            .categories
+            .iter()
+            .any(|category| category == "android.intent.category.LEANBACK_LAUNCHER"));
+
+        let manifest = generate(json!({ "tv": false }), json!({}), false).unwrap();
+        assert!(manifest.uses_feature.is_empty());
+    }
+
+    /// A fresh directory for files the manifest is generated from.
+    fn package_root(name: &str) -> Utf8PathBuf {
+        let dir = env::temp_dir().join(format!("cargo-ori-{}-{}", name, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        Utf8PathBuf::try_from(dir).unwrap()
+    }
+
+    #[test]
+    fn debuggable_only_for_debug_builds() {
+        let manifest = generate(json!({}), json!({}), true).unwrap();
+        assert_eq!(manifest.application.debuggable, Some(true));
+
+        let manifest = generate(json!({}), json!({}), false).unwrap();
+        assert_eq!(manifest.application.debuggable, None);
+    }
+
+    #[test]
+    fn release_builds_ignore_debuggable_overlay() {
+        let root = package_root("debuggable-overlay");
+        let overlay = r#"<manifest xmlns:android="http://schemas.android.com/apk/res/android">
+            <application android:debuggable="true" />
+        </manifest>"#;
+        fs::write(root.join("overlay.xml"), overlay).unwrap();
+
+        let apk_metadata = json!({ "manifest-overlay": "overlay.xml" });
+        let manifest = generate_in(&root, apk_metadata, json!({}), false).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(manifest.application.debuggable, None);
+    }
+
+    #[test]
+    fn overlay_package_is_validated() {
+        let root = package_root("package-overlay");
+        let overlay = r#"<manifest package="com.example.my-app" />"#;
+        fs::write(root.join("overlay.xml"), overlay).unwrap();
+
+        let apk_metadata = json!({ "manifest-overlay": "overlay.xml" });
+        let result = generate_in(&root, apk_metadata, json!({}), false);
+        fs::remove_dir_all(&root).unwrap();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn placeholders_are_substituted_before_validation() {
+        let apk_metadata = json!({
+            "application-id": "com.${org}.app",
+            "launch-mode": "${launch-mode}",
+            "manifest-placeholders": { "org": "acme", "launch-mode": "singleTask" },
+        });
+        let manifest = generate(apk_metadata, json!({}), false).unwrap();
+
+        assert_eq!(manifest.package.as_deref(), Some("com.acme.app"));
+        assert_eq!(
+            manifest.application.activities[0].launch_mode.as_deref(),
+            Some("singleTask")
+        );
+
+        let apk_metadata = json!({
+            "launch-mode": "${launch-mode}",
+            "manifest-placeholders": { "launch-mode": "sometimes" },
+        });
+        assert!(generate(apk_metadata, json!({}), false).is_err());
+    }
+
+    fn cdylib_artifact(crate_types: &[&str], filenames: &[&Utf8Path]) -> cargo_metadata::Artifact {
+        serde_json::from_value(json!({
+            "package_id": "my-app 0.3.1 (path+file:///my-app)",
+            "target": {
+                "name": "my_app",
+                "kind": crate_types,
+                "crate_types": crate_types,
+                "src_path": "/my-app/src/lib.rs",
+            },
+            "profile": {
+                "opt_level": "0",
+                "debug_assertions": true,
+                "overflow_checks": true,
+                "test": false,
+            },
+            "features": [],
+            "filenames": filenames,
+            "executable": null,
+            "fresh": false,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn artifact_cdylib_uses_the_reported_path() {
+        let root = package_root("artifact");
+        let rlib = root.join("libmy_app.rlib");
+        let cdylib = root.join("libmy_app.so");
+        fs::write(&cdylib, "").unwrap();
+
+        let artifact = cdylib_artifact(&["rlib", "cdylib"], &[&rlib, &cdylib]);
+        let path = artifact_cdylib(&artifact, Utf8Path::new("/nonexistent"));
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(path.unwrap(), cdylib);
+    }
+
+    #[test]
+    fn artifact_cdylib_of_container_paths() {
+        let root = package_root("artifact-container");
+        let lib_dir = root.join("target/aarch64-linux-android/debug");
+        fs::create_dir_all(&lib_dir).unwrap();
+        fs::write(lib_dir.join("libmy_app.so"), "").unwrap();
+
+        // the path cross reports with the workspace mounted at the root
+        let reported = Utf8Path::new("/target/aarch64-linux-android/debug/libmy_app.so");
+        let artifact = cdylib_artifact(&["cdylib"], &[reported]);
+        let path = artifact_cdylib(&artifact, &root);
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(path.unwrap(), lib_dir.join("libmy_app.so"));
+    }
+
+    #[test]
+    fn artifact_cdylib_of_a_target_dir_outside_the_workspace() {
+        let root = package_root("artifact-target-dir");
+        let workspace_root = root.join("workspace");
+        let lib_dir = root.join("elsewhere/target/aarch64-linux-android/debug");
+        fs::create_dir_all(workspace_root.join("target/aarch64-linux-android/debug")).unwrap();
+        fs::create_dir_all(&lib_dir).unwrap();
+        fs::write(lib_dir.join("libmy_app.so"), "").unwrap();
+
+        // reported as is, not rejoined to the workspace
+        let artifact = cdylib_artifact(&["cdylib"], &[&lib_dir.join("libmy_app.so")]);
+        let path = artifact_cdylib(&artifact, &workspace_root);
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(path.unwrap(), lib_dir.join("libmy_app.so"));
+    }
+
+    #[test]
+    fn artifact_cdylib_requires_a_cdylib() {
+        let artifact = cdylib_artifact(&["rlib"], &[Utf8Path::new("/nonexistent/libmy_app.rlib")]);
+        assert!(artifact_cdylib(&artifact, Utf8Path::new("/nonexistent")).is_err());
+
+        let artifact = cdylib_artifact(&["cdylib"], &[Utf8Path::new("/nonexistent/libmy_app.so")]);
+        assert!(artifact_cdylib(&artifact, Utf8Path::new("/nonexistent")).is_err());
+    }
+
+    /// The target triple of the host, which needs no extra toolchain.
+    fn host_target() -> String {
+        let output = process::Command::new("rustc").arg("-vV").output().unwrap();
+        let version = String::from_utf8(output.stdout).unwrap();
+
+        version
+            .lines()
+            .find_map(|line| line.strip_prefix("host: "))
+            .map(String::from)
+            .expect("rustc reports the host")
+    }
+
+    #[test]
+    fn extra_rustflags_keep_configured_cfg_rustflags() {
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/rustflags");
+        let target = host_target();
+
+        let options = BuildOptions::parse_from(["build", "--rustflags", "--cfg extra_rustflags"]);
+        let Some(Rustflags::Config(config)) = rustflags(&target, &options) else {
+            panic!("extra rustflags go through --config without RUSTFLAGS");
+        };
+
+        // the fixture fails to compile without either flag
+        let target_dir = env::temp_dir().join(format!("cargo-ori-rustflags-{}", process::id()));
+        let output = process::Command::new(env::var_os("CARGO").unwrap_or("cargo".into()))
+            .current_dir(&fixture)
+            .arg("check")
+            .arg("--target")
+            .arg(&target)
+            .arg("--target-dir")
+            .arg(&target_dir)
+            .arg("--config")
+            .arg(&config)
+            .env_remove("RUSTFLAGS")
+            .env_remove("CARGO_ENCODED_RUSTFLAGS")
+            .output()
+            .unwrap();
+        let _ = fs::remove_dir_all(&target_dir);
+
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    #[test]
+    fn universal_apk_path_of_targets() {
+        let target_dir = Utf8Path::new("/my-app/target");
+        let lib = target_dir.join("aarch64-linux-android/release/libmy_app.so");
+        let other = target_dir.join("x86_64-linux-android/release/libmy_app.so");
+
+        assert_eq!(
+            universal_apk_path(target_dir, "my-app", &[&lib]),
+            target_dir.join("aarch64-linux-android/release/my-app.apk")
+        );
+        assert_eq!(
+            universal_apk_path(target_dir, "my-app", &[&lib, &other]),
+            target_dir.join("apk/my-app.apk")
+        );
+    }
+
+    #[test]
+    fn custom_profiles_are_stripped_and_16kb_aligned() {
+        for args in [&["build"][..], &["build", "--profile", "dev"]] {
+            let options = BuildOptions::parse_from(args);
+            assert!(options.is_debug());
+            assert!(!options.strips());
+            assert_eq!(options.page_size(), 4096);
+        }
+
+        for args in [
+            &["build", "--release"][..],
+            &["build", "--profile", "release"],
+            &["build", "--profile", "dist"],
+        ] {
+            let options = BuildOptions::parse_from(args);
+            assert!(!options.is_debug());
+            assert!(options.strips(), "{:?}", args);
+            assert_eq!(options.page_size(), 16384, "{:?}", args);
+        }
+
+        let options = BuildOptions::parse_from(["build", "--profile", "dist", "--no-strip"]);
+        assert!(!options.strips());
+    }
+
+    #[test]
+    fn manifest_application_meta_data() {
+        let apk_metadata = json!({
+            "application-meta-data": { "com.google.android.geo.API_KEY": "abc123" },
+        });
+        let manifest = generate(apk_metadata, json!({}), false).unwrap();
+
+        let meta_data = &manifest.application.meta_data[0];
+        assert_eq!(meta_data.name, "com.google.android.geo.API_KEY");
+        assert_eq!(meta_data.value, "abc123");
+
+        for meta_data in [
+            json!({ "com.example.config": "@xml/config" }),
+            json!({ " ": "value" }),
+        ] {
+            let apk_metadata = json!({ "application-meta-data": meta_data });
+            assert!(generate(apk_metadata, json!({}), false).is_err());
+        }
+    }
+
+    #[test]
+    fn signing_pem_from_environment() {
+        let signing: Signing =
+            serde_json::from_value(json!({ "pem": "${HOME}/release.pem" })).unwrap();
+        let home = env::var("HOME").unwrap();
+
+        assert_eq!(
+            signing.pem_path(Utf8Path::new("/my-app")).unwrap(),
+            Path::new(&home).join("release.pem")
+        );
+    }
+
+    #[test]
+    fn signing_keystores_are_unknown() {
+        let signing = json!({ "keystore": "release.jks", "key-alias": "upload" });
+        assert!(serde_json::from_value::<Signing>(signing).is_err());
     }
 }
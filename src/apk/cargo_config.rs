@@ -0,0 +1,302 @@
+//! Lookup of values of cargo's configuration, e.g. the default `build.target`
+//! used when neither `--target` nor a device gives one.
+//!
+//! Values are read the way cargo reads them, from the `--config` overrides,
+//! then the `CARGO_*` environment variables, then the `.cargo/config.toml`
+//! files from the current directory up and in `CARGO_HOME`. Files that can't
+//! be read or parsed are skipped, cargo reports them when building.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// The `build.target` of cargo's configuration, with the `--config`
+/// overrides in `overrides`.
+pub fn build_target(overrides: &[String]) -> Vec<String> {
+    let env = env::var("CARGO_BUILD_TARGET").ok().map(toml::Value::String);
+
+    let value = override_tables(overrides)
+        .iter()
+        .find_map(|table| get(table, &["build", "target"]).cloned())
+        .or(env)
+        .or_else(|| {
+            file_tables()
+                .iter()
+                .find_map(|table| get(table, &["build", "target"]).cloned())
+        });
+
+    let targets = value.map(|value| targets(&value)).unwrap_or_default();
+
+    if !targets.is_empty() {
+        tracing::debug!(?targets, "using build.target");
+    }
+
+    targets
+}
+
+/// The `build.rustflags` of cargo's configuration, which apply to `target`
+/// only while no `target.<triple>.rustflags` or `target.<cfg>.rustflags` are
+/// configured, empty otherwise.
+///
+/// `target.<cfg>` tables are assumed to match, as that needs the cfg values
+/// of the target.
+pub fn build_rustflags(overrides: &[String], target: &str) -> Vec<String> {
+    let env_key = format!(
+        "CARGO_TARGET_{}_RUSTFLAGS",
+        target.to_uppercase().replace(['-', '.'], "_")
+    );
+
+    let overrides = override_tables(overrides);
+    let files = file_tables();
+    let tables = || overrides.iter().chain(files.iter());
+
+    let target_flags = tables().any(|table| {
+        let Some(targets) = get(table, &["target"]).and_then(toml::Value::as_table) else {
+            return false;
+        };
+
+        targets.iter().any(|(key, value)| {
+            (key == target || key.starts_with("cfg(")) && value.get("rustflags").is_some()
+        })
+    });
+
+    if target_flags || env::var_os(env_key).is_some() {
+        return Vec::new();
+    }
+
+    // arrays of every source are joined, the highest precedence last
+    let mut rustflags: Vec<String> = files
+        .iter()
+        .rev()
+        .filter_map(|table| get(table, &["build", "rustflags"]))
+        .flat_map(flags)
+        .collect();
+
+    if let Ok(env) = env::var("CARGO_BUILD_RUSTFLAGS") {
+        rustflags.extend(env.split_whitespace().map(String::from));
+    }
+
+    rustflags.extend(
+        overrides
+            .iter()
+            .rev()
+            .filter_map(|table| get(table, &["build", "rustflags"]))
+            .flat_map(flags),
+    );
+
+    rustflags
+}
+
+/// The tables of the `--config` overrides, either `key=value` pairs or paths
+/// of extra configuration files, the last one first.
+fn override_tables(overrides: &[String]) -> Vec<toml::Table> {
+    overrides
+        .iter()
+        .rev()
+        .filter_map(|config| match config.parse::<toml::Table>() {
+            Ok(table) => Some(table),
+            Err(_) => read_table(Path::new(config)),
+        })
+        .collect()
+}
+
+/// The tables of the configuration files cargo reads, the closest first.
+fn file_tables() -> Vec<toml::Table> {
+    let mut dirs: Vec<PathBuf> = match env::current_dir() {
+        Ok(dir) => dir.ancestors().map(|dir| dir.join(".cargo")).collect(),
+        Err(_) => Vec::new(),
+    };
+
+    let cargo_home = match env::var_os("CARGO_HOME") {
+        Some(cargo_home) => Some(PathBuf::from(cargo_home)),
+        None => env::var_os("HOME")
+            .or_else(|| env::var_os("USERPROFILE"))
+            .map(|home| PathBuf::from(home).join(".cargo")),
+    };
+
+    if let Some(cargo_home) = cargo_home {
+        if !dirs.contains(&cargo_home) {
+            dirs.push(cargo_home);
+        }
+    }
+
+    dirs.iter()
+        .filter_map(|dir| {
+            // `config` without the extension is still read by cargo
+            ["config.toml", "config"]
+                .iter()
+                .map(|name| dir.join(name))
+                .find(|path| path.is_file())
+        })
+        .filter_map(|path| read_table(&path))
+        .collect()
+}
+
+fn read_table(path: &Path) -> Option<toml::Table> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) => {
+            tracing::debug!(%err, path = %path.display(), "failed to read cargo config");
+            return None;
+        }
+    };
+
+    match contents.parse() {
+        Ok(table) => Some(table),
+        Err(err) => {
+            tracing::debug!(%err, path = %path.display(), "malformed cargo config");
+            None
+        }
+    }
+}
+
+/// The value at the dotted `key` of `table`.
+fn get<'a>(table: &'a toml::Table, key: &[&str]) -> Option<&'a toml::Value> {
+    let (last, tables) = key.split_last()?;
+
+    let mut table = table;
+
+    for key in tables {
+        table = table.get(*key)?.as_table()?;
+    }
+
+    table.get(*last)
+}
+
+/// Targets given as a string or an array of strings.
+fn targets(value: &toml::Value) -> Vec<String> {
+    match value {
+        toml::Value::String(target) => vec![target.clone()],
+        value => strings(value),
+    }
+}
+
+/// Flags given as a whitespace separated string or an array of strings.
+fn flags(value: &toml::Value) -> Vec<String> {
+    match value {
+        toml::Value::String(flags) => flags.split_whitespace().map(String::from).collect(),
+        value => strings(value),
+    }
+}
+
+fn strings(value: &toml::Value) -> Vec<String> {
+    let Some(items) = value.as_array() else {
+        return Vec::new();
+    };
+
+    items
+        .iter()
+        .filter_map(|item| item.as_str().map(String::from))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(toml: &str) -> toml::Value {
+        let table: toml::Table = format!("value = {}", toml).parse().unwrap();
+        table["value"].clone()
+    }
+
+    #[test]
+    fn targets_from_string() {
+        let value = value(r#""aarch64-linux-android""#);
+        assert_eq!(targets(&value), ["aarch64-linux-android"]);
+    }
+
+    #[test]
+    fn targets_from_array() {
+        let value = value(
+            r#"[
+                "aarch64-linux-android", # phones
+                "x86_64-linux-android",
+            ]"#,
+        );
+        assert_eq!(
+            targets(&value),
+            ["aarch64-linux-android", "x86_64-linux-android"]
+        );
+    }
+
+    #[test]
+    fn flags_from_string_split_on_whitespace() {
+        let value = value(r#""-C target-cpu=native  -Dwarnings""#);
+        assert_eq!(flags(&value), ["-C", "target-cpu=native", "-Dwarnings"]);
+    }
+
+    #[test]
+    fn flags_from_array_keep_spaces() {
+        let value = value(r#"["-C", "link-arg=-Wl,--foo bar"]"#);
+        assert_eq!(flags(&value), ["-C", "link-arg=-Wl,--foo bar"]);
+    }
+
+    #[test]
+    fn targets_of_other_values_are_empty() {
+        assert!(targets(&value("42")).is_empty());
+        assert!(targets(&value(r#"{ target = "x86_64-linux-android" }"#)).is_empty());
+    }
+
+    #[test]
+    fn build_target_from_overrides() {
+        let overrides = [
+            String::from(r#"build.target = "aarch64-linux-android""#),
+            String::from(r#"build.target = ["x86_64-linux-android"]"#),
+        ];
+
+        // the last one takes precedence, like in cargo
+        assert_eq!(build_target(&overrides), ["x86_64-linux-android"]);
+    }
+
+    #[test]
+    fn build_target_from_override_files() {
+        let path = env::temp_dir().join(format!("cargo-ori-config-{}.toml", std::process::id()));
+        fs::write(&path, "[build]\ntarget = \"armv7-linux-androideabi\"\n").unwrap();
+
+        let overrides = [path.display().to_string()];
+        let targets = build_target(&overrides);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(targets, ["armv7-linux-androideabi"]);
+    }
+
+    #[test]
+    fn build_rustflags_from_overrides() {
+        let overrides = [
+            String::from(r#"build.rustflags = ["--cfg", "first"]"#),
+            String::from(r#"build.rustflags = "--cfg second""#),
+        ];
+
+        assert_eq!(
+            build_rustflags(&overrides, "x86_64-linux-android"),
+            ["--cfg", "first", "--cfg", "second"]
+        );
+    }
+
+    #[test]
+    fn build_rustflags_are_unused_with_target_rustflags() {
+        for target_flags in [
+            r#"target.x86_64-linux-android.rustflags = ["--cfg", "triple"]"#,
+            r#"target.'cfg(target_os = "android")'.rustflags = ["--cfg", "android"]"#,
+        ] {
+            let overrides = [
+                String::from(r#"build.rustflags = ["--cfg", "build"]"#),
+                String::from(target_flags),
+            ];
+
+            assert!(build_rustflags(&overrides, "x86_64-linux-android").is_empty());
+        }
+
+        // only the tables of the target count
+        let overrides = [
+            String::from(r#"build.rustflags = ["--cfg", "build"]"#),
+            String::from(r#"target.aarch64-linux-android.rustflags = ["--cfg", "other"]"#),
+        ];
+
+        assert_eq!(
+            build_rustflags(&overrides, "x86_64-linux-android"),
+            ["--cfg", "build"]
+        );
+    }
+}
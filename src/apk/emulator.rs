@@ -0,0 +1,183 @@
+//! Booting an Android Virtual Device to install on with `--emulator`.
+
+use std::{
+    collections::BTreeSet,
+    env,
+    path::PathBuf,
+    process, thread,
+    time::{Duration, Instant},
+};
+
+use eyre::Context;
+use owo_colors::OwoColorize;
+use tracing::Level;
+
+use super::{adb_devices, adb_output, echo_command, wait_for_device, BOOT_POLL_INTERVAL};
+
+/// How long an emulator may take to boot.
+const BOOT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// A booted emulator, shut down on drop if it was started with
+/// `--kill-emulator`.
+pub struct Emulator {
+    pub serial: String,
+    kill: bool,
+}
+
+impl Drop for Emulator {
+    fn drop(&mut self) {
+        if !self.kill {
+            return;
+        }
+
+        status!("  {} emulator `{}`", "Stopping".green().bold(), self.serial);
+
+        let mut command = process::Command::new("adb");
+        command.arg("-s").arg(&self.serial).arg("emu").arg("kill");
+
+        if let Err(err) = adb_output(&mut command, Level::DEBUG) {
+            status!(
+                "  {} failed to stop emulator `{}`: {}",
+                "Warning".yellow().bold(),
+                self.serial,
+                err
+            );
+        }
+    }
+}
+
+/// Boot the AVD `avd` and wait for it to finish booting, reusing it if it is
+/// already running. With `kill` an emulator started here is shut down when
+/// the returned [`Emulator`] is dropped.
+pub fn boot(avd: &str, kill: bool) -> eyre::Result<Emulator> {
+    let deadline = Instant::now() + BOOT_TIMEOUT;
+
+    let mut running = BTreeSet::new();
+
+    for device in adb_devices()? {
+        if !device.id.starts_with("emulator-") {
+            continue;
+        }
+
+        if avd_name(&device.id)?.as_deref() == Some(avd) {
+            status!(
+                "  {} running emulator `{}` ({})",
+                "Using".green().bold(),
+                avd,
+                device.id
+            );
+
+            wait_for_boot(avd, &device.id, deadline)?;
+
+            return Ok(Emulator {
+                serial: device.id,
+                kill: false,
+            });
+        }
+
+        running.insert(device.id);
+    }
+
+    status!("  {} emulator `{}`", "Booting".green().bold(), avd);
+
+    let mut command = process::Command::new(emulator_binary());
+
+    command
+        .arg("-avd")
+        .arg(avd)
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::null())
+        .stderr(process::Stdio::null());
+
+    echo_command(&command, Level::DEBUG);
+
+    let mut child = command
+        .spawn()
+        .wrap_err("Failed to start `emulator`, set `ANDROID_HOME` or add it to `PATH`")?;
+
+    let serial = loop {
+        if let Some(status) = child.try_wait()? {
+            eyre::bail!("Emulator `{}` exited with {}", avd, status);
+        }
+
+        let serial = adb_devices()?
+            .into_iter()
+            .map(|device| device.id)
+            .find(|id| id.starts_with("emulator-") && !running.contains(id));
+
+        if let Some(serial) = serial {
+            break serial;
+        }
+
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            eyre::bail!(
+                "Emulator `{}` did not start within {}s",
+                avd,
+                BOOT_TIMEOUT.as_secs()
+            );
+        }
+
+        thread::sleep(BOOT_POLL_INTERVAL);
+    };
+
+    // from here on the emulator is shut down on error when asked to
+    let emulator = Emulator { serial, kill };
+
+    wait_for_boot(avd, &emulator.serial, deadline)?;
+
+    Ok(emulator)
+}
+
+fn wait_for_boot(avd: &str, serial: &str, deadline: Instant) -> eyre::Result<()> {
+    let timeout = deadline.saturating_duration_since(Instant::now());
+    wait_for_device(Some(serial), timeout)
+        .wrap_err_with(|| format!("Emulator `{}` did not boot", avd))?;
+
+    status!(
+        "  {} emulator `{}` ({})",
+        "Booted".green().bold(),
+        avd,
+        serial
+    );
+
+    Ok(())
+}
+
+/// The AVD an emulator is running, from `adb emu avd name`.
+fn avd_name(serial: &str) -> eyre::Result<Option<String>> {
+    let mut command = process::Command::new("adb");
+    command
+        .arg("-s")
+        .arg(serial)
+        .arg("emu")
+        .arg("avd")
+        .arg("name");
+
+    let output = adb_output(&mut command, Level::TRACE)?;
+
+    if !output.status.success() {
+        return Ok(None);
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(stdout.lines().next().map(|line| String::from(line.trim())))
+}
+
+/// The `emulator` of the SDK at `ANDROID_HOME` or `ANDROID_SDK_ROOT`, or the
+/// one on `PATH`.
+fn emulator_binary() -> PathBuf {
+    let name = format!("emulator{}", env::consts::EXE_SUFFIX);
+
+    for var in ["ANDROID_HOME", "ANDROID_SDK_ROOT"] {
+        if let Some(sdk) = env::var_os(var) {
+            let path = PathBuf::from(sdk).join("emulator").join(&name);
+
+            if path.exists() {
+                return path;
+            }
+        }
+    }
+
+    PathBuf::from(name)
+}
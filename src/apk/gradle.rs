@@ -0,0 +1,184 @@
+//! Export of a Gradle project for Android Studio with `apk export-gradle`.
+//!
+//! The export is a one-way snapshot of the generated manifest, the built
+//! libraries and the sources of the bundled activity, changes made to it are
+//! not picked up by `cargo ori`.
+
+use std::{fs, path::Path};
+
+use cargo_metadata::camino::Utf8Path;
+use eyre::Context;
+
+const ORI_ACTIVITY_JAVA: &str =
+    include_str!("../../android/library/src/main/java/ori/oriactivity/OriActivity.java");
+
+const ORI_EDIT_TEXT_JAVA: &str =
+    include_str!("../../android/library/src/main/java/ori/oriactivity/OriEditText.java");
+
+/// The namespace of the bundled activity, which owns the generated `R`.
+const NAMESPACE: &str = "ori.oriactivity";
+
+/// Launcher icon sizes by density.
+const ICON_DENSITIES: &[(&str, u32)] = &[
+    ("mdpi", 48),
+    ("hdpi", 72),
+    ("xhdpi", 96),
+    ("xxhdpi", 144),
+    ("xxxhdpi", 192),
+];
+
+/// Write a Gradle project to `out` building `manifest` with the libraries
+/// `libs`.
+pub fn export(
+    out: &Path,
+    name: &str,
+    manifest: &apk::AndroidManifest,
+    libs: &[(apk::Target, &Utf8Path)],
+    icon: Option<&Utf8Path>,
+) -> eyre::Result<()> {
+    let application_id = manifest
+        .package
+        .as_deref()
+        .ok_or_else(|| eyre::eyre!("Manifest has no package"))?;
+
+    let main = out.join("app").join("src").join("main");
+
+    write(&out.join("settings.gradle"), &settings_gradle(name))?;
+    write(&out.join("build.gradle"), ROOT_BUILD_GRADLE)?;
+    write(
+        &out.join("app").join("build.gradle"),
+        &app_build_gradle(application_id, manifest),
+    )?;
+
+    let mut manifest = manifest.clone();
+
+    // set by the Gradle build instead, where the manifest must not have them
+    manifest.package = None;
+    manifest.version_code = None;
+    manifest.version_name = None;
+    manifest.compile_sdk_version = None;
+    manifest.compile_sdk_version_codename = None;
+    manifest.platform_build_version_code = None;
+    manifest.platform_build_version_name = None;
+    manifest.sdk = Default::default();
+    manifest.application.debuggable = None;
+
+    if let Some(icon) = icon {
+        let mut scaler = xcommon::Scaler::open(icon)
+            .map_err(|e| eyre::eyre!("Failed to open icon `{}`: {}", icon, e))?;
+        scaler.optimize();
+
+        for (density, size) in ICON_DENSITIES {
+            let dir = main.join("res").join(format!("mipmap-{}", density));
+            fs::create_dir_all(&dir)?;
+
+            let mut file = fs::File::create(dir.join("icon.png"))?;
+            scaler
+                .write(&mut file, xcommon::ScalerOpts::new(*size))
+                .map_err(|e| eyre::eyre!("{}", e))?;
+        }
+
+        manifest.application.icon = Some(String::from("@mipmap/icon"));
+    }
+
+    write(
+        &main.join("AndroidManifest.xml"),
+        &format!("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n{}\n", manifest),
+    )?;
+
+    let java = main.join("java").join("ori").join("oriactivity");
+    write(&java.join("OriActivity.java"), ORI_ACTIVITY_JAVA)?;
+    write(&java.join("OriEditText.java"), ORI_EDIT_TEXT_JAVA)?;
+
+    for (target, lib_path) in libs {
+        let dir = main.join("jniLibs").join(target.android_abi());
+        fs::create_dir_all(&dir)?;
+
+        let file_name = lib_path.file_name().expect("lib_path has file name");
+        fs::copy(lib_path, dir.join(file_name))
+            .wrap_err_with(|| format!("Failed to copy `{}`", lib_path))?;
+    }
+
+    Ok(())
+}
+
+fn write(path: &Path, contents: &str) -> eyre::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::write(path, contents).wrap_err_with(|| format!("Failed to write `{}`", path.display()))
+}
+
+const ROOT_BUILD_GRADLE: &str = "\
+plugins {
+    id 'com.android.application' version '8.6.0' apply false
+}
+";
+
+fn settings_gradle(name: &str) -> String {
+    format!(
+        "\
+pluginManagement {{
+    repositories {{
+        gradlePluginPortal()
+        google()
+        mavenCentral()
+    }}
+}}
+dependencyResolutionManagement {{
+    repositoriesMode.set(RepositoriesMode.FAIL_ON_PROJECT_REPOS)
+    repositories {{
+        google()
+        mavenCentral()
+    }}
+}}
+
+rootProject.name = \"{}\"
+include \":app\"
+",
+        name
+    )
+}
+
+fn app_build_gradle(application_id: &str, manifest: &apk::AndroidManifest) -> String {
+    let sdk = &manifest.sdk;
+
+    format!(
+        "\
+plugins {{
+    id 'com.android.application'
+}}
+
+android {{
+    compileSdk {compile_sdk}
+
+    defaultConfig {{
+        applicationId \"{application_id}\"
+        minSdk {min_sdk}
+        targetSdk {target_sdk}
+        versionCode {version_code}
+        versionName \"{version_name}\"
+    }}
+
+    buildTypes {{
+        release {{
+            minifyEnabled false
+        }}
+    }}
+
+    namespace \"{NAMESPACE}\"
+
+    compileOptions {{
+        sourceCompatibility JavaVersion.VERSION_1_8
+        targetCompatibility JavaVersion.VERSION_1_8
+    }}
+}}
+",
+        compile_sdk = manifest.compile_sdk_version.unwrap_or(34),
+        min_sdk = sdk.min_sdk_version.unwrap_or(21),
+        target_sdk = sdk.target_sdk_version.unwrap_or(34),
+        version_code = manifest.version_code.unwrap_or(1),
+        version_name = manifest.version_name.as_deref().unwrap_or("1.0"),
+    )
+}
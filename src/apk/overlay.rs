@@ -0,0 +1,369 @@
+//! Merging of a user supplied `AndroidManifest.xml` into the generated
+//! manifest.
+//!
+//! Attributes in the overlay win over generated ones, elements are added to
+//! the generated ones. Activities with the same `android:name` are merged.
+
+use std::{fmt::Display, str::FromStr};
+
+use apk::manifest::{Activity, Application, Feature, IntentFilter, IntentFilterData, Permission};
+use roxmltree::{Attribute, Node};
+
+const ANDROID_NS: &str = "http://schemas.android.com/apk/res/android";
+
+pub fn merge(manifest: &mut apk::AndroidManifest, xml: &str) -> eyre::Result<()> {
+    let document = roxmltree::Document::parse(xml)?;
+    let root = document.root_element();
+
+    if root.tag_name().name() != "manifest" {
+        eyre::bail!("Manifest overlay must have a `<manifest>` root element");
+    }
+
+    for attr in root.attributes() {
+        match (attr.namespace(), attr.name()) {
+            (None, "package") => manifest.package = Some(String::from(attr.value())),
+            (Some(ANDROID_NS), "versionCode") => manifest.version_code = Some(parse(&attr)?),
+            (Some(ANDROID_NS), "versionName") => {
+                manifest.version_name = Some(String::from(attr.value()))
+            }
+            _ => unsupported_attribute(root, &attr)?,
+        }
+    }
+
+    for child in root.children().filter(Node::is_element) {
+        match child.tag_name().name() {
+            "uses-sdk" => merge_sdk(&mut manifest.sdk, child)?,
+            "uses-feature" => manifest.uses_feature.push(feature(child)?),
+            "uses-permission" => manifest.uses_permission.push(permission(child)?),
+            "application" => merge_application(&mut manifest.application, child)?,
+            _ => unsupported_element(child)?,
+        }
+    }
+
+    Ok(())
+}
+
+fn merge_sdk(sdk: &mut apk::manifest::Sdk, node: Node) -> eyre::Result<()> {
+    for attr in node.attributes() {
+        match (attr.namespace(), attr.name()) {
+            (Some(ANDROID_NS), "minSdkVersion") => sdk.min_sdk_version = Some(parse(&attr)?),
+            (Some(ANDROID_NS), "targetSdkVersion") => sdk.target_sdk_version = Some(parse(&attr)?),
+            (Some(ANDROID_NS), "maxSdkVersion") => sdk.max_sdk_version = Some(parse(&attr)?),
+            _ => unsupported_attribute(node, &attr)?,
+        }
+    }
+
+    Ok(())
+}
+
+fn feature(node: Node) -> eyre::Result<Feature> {
+    let mut feature = Feature::default();
+
+    for attr in node.attributes() {
+        match (attr.namespace(), attr.name()) {
+            (Some(ANDROID_NS), "name") => feature.name = Some(String::from(attr.value())),
+            (Some(ANDROID_NS), "required") => feature.required = Some(parse(&attr)?),
+            (Some(ANDROID_NS), "version") => feature.version = Some(parse(&attr)?),
+            (Some(ANDROID_NS), "glEsVersion") => {
+                let version = attr.value().trim_start_matches("0x");
+                let version = u32::from_str_radix(version, 16)
+                    .map_err(|_| eyre::eyre!("Malformed `glEsVersion` `{}`", attr.value()))?;
+
+                feature.opengles_version = Some(((version >> 16) as u8, version as u8));
+            }
+            _ => unsupported_attribute(node, &attr)?,
+        }
+    }
+
+    Ok(feature)
+}
+
+fn permission(node: Node) -> eyre::Result<Permission> {
+    let mut name = None;
+    let mut max_sdk_version = None;
+
+    for attr in node.attributes() {
+        match (attr.namespace(), attr.name()) {
+            (Some(ANDROID_NS), "name") => name = Some(String::from(attr.value())),
+            (Some(ANDROID_NS), "maxSdkVersion") => max_sdk_version = Some(parse(&attr)?),
+            _ => unsupported_attribute(node, &attr)?,
+        }
+    }
+
+    Ok(Permission {
+        name: name.ok_or_else(|| eyre::eyre!("`<uses-permission>` requires `android:name`"))?,
+        max_sdk_version,
+    })
+}
+
+fn merge_application(application: &mut Application, node: Node) -> eyre::Result<()> {
+    for attr in node.attributes() {
+        match (attr.namespace(), attr.name()) {
+            (Some(ANDROID_NS), "debuggable") => application.debuggable = Some(parse(&attr)?),
+            (Some(ANDROID_NS), "theme") => application.theme = Some(String::from(attr.value())),
+            (Some(ANDROID_NS), "hasCode") => application.has_code = Some(parse(&attr)?),
+            (Some(ANDROID_NS), "icon") => application.icon = Some(String::from(attr.value())),
+            (Some(ANDROID_NS), "label") => application.label = Some(String::from(attr.value())),
+            (Some(ANDROID_NS), "appComponentFactory") => {
+                application.app_component_factory = Some(String::from(attr.value()))
+            }
+            _ => unsupported_attribute(node, &attr)?,
+        }
+    }
+
+    for child in node.children().filter(Node::is_element) {
+        match child.tag_name().name() {
+            "meta-data" => application.meta_data.push(meta_data(child)?),
+            "activity" => {
+                let name = child.attribute((ANDROID_NS, "name"));
+
+                let existing = application
+                    .activities
+                    .iter_mut()
+                    .find(|activity| name.is_some() && activity.name.as_deref() == name);
+
+                match existing {
+                    Some(activity) => merge_activity(activity, child)?,
+                    None => {
+                        let mut activity = Activity::default();
+                        merge_activity(&mut activity, child)?;
+                        application.activities.push(activity);
+                    }
+                }
+            }
+            _ => unsupported_element(child)?,
+        }
+    }
+
+    Ok(())
+}
+
+fn merge_activity(activity: &mut Activity, node: Node) -> eyre::Result<()> {
+    for attr in node.attributes() {
+        let value = String::from(attr.value());
+
+        match (attr.namespace(), attr.name()) {
+            (Some(ANDROID_NS), "configChanges") => activity.config_changes = Some(value),
+            (Some(ANDROID_NS), "label") => activity.label = Some(value),
+            (Some(ANDROID_NS), "launchMode") => activity.launch_mode = Some(value),
+            (Some(ANDROID_NS), "name") => activity.name = Some(value),
+            (Some(ANDROID_NS), "screenOrientation") => activity.orientation = Some(value),
+            (Some(ANDROID_NS), "windowSoftInputMode") => {
+                activity.window_soft_input_mode = Some(value)
+            }
+            (Some(ANDROID_NS), "exported") => activity.exported = Some(parse(&attr)?),
+            (Some(ANDROID_NS), "hardwareAccelerated") => {
+                activity.hardware_accelerated = Some(parse(&attr)?)
+            }
+            _ => unsupported_attribute(node, &attr)?,
+        }
+    }
+
+    for child in node.children().filter(Node::is_element) {
+        match child.tag_name().name() {
+            "meta-data" => activity.meta_data.push(meta_data(child)?),
+            "intent-filter" => activity.intent_filters.push(intent_filter(child)?),
+            _ => unsupported_element(child)?,
+        }
+    }
+
+    Ok(())
+}
+
+fn meta_data(node: Node) -> eyre::Result<apk::manifest::MetaData> {
+    let mut name = None;
+    let mut value = None;
+
+    for attr in node.attributes() {
+        match (attr.namespace(), attr.name()) {
+            (Some(ANDROID_NS), "name") => name = Some(String::from(attr.value())),
+            (Some(ANDROID_NS), "value") => value = Some(String::from(attr.value())),
+            _ => unsupported_attribute(node, &attr)?,
+        }
+    }
+
+    match (name, value) {
+        (Some(name), Some(value)) => Ok(apk::manifest::MetaData { name, value }),
+        _ => eyre::bail!("`<meta-data>` requires `android:name` and `android:value`"),
+    }
+}
+
+fn intent_filter(node: Node) -> eyre::Result<IntentFilter> {
+    let mut filter = IntentFilter::default();
+
+    if let Some(attr) = node.attributes().next() {
+        unsupported_attribute(node, &attr)?;
+    }
+
+    for child in node.children().filter(Node::is_element) {
+        match child.tag_name().name() {
+            "action" => filter.actions.push(required_name(child)?),
+            "category" => filter.categories.push(required_name(child)?),
+            "data" => filter.data.push(intent_filter_data(child)?),
+            _ => unsupported_element(child)?,
+        }
+    }
+
+    Ok(filter)
+}
+
+fn intent_filter_data(node: Node) -> eyre::Result<IntentFilterData> {
+    let mut data = IntentFilterData::default();
+
+    for attr in node.attributes() {
+        let value = Some(String::from(attr.value()));
+
+        match (attr.namespace(), attr.name()) {
+            (Some(ANDROID_NS), "scheme") => data.scheme = value,
+            (Some(ANDROID_NS), "host") => data.host = value,
+            (Some(ANDROID_NS), "port") => data.port = value,
+            (Some(ANDROID_NS), "path") => data.path = value,
+            (Some(ANDROID_NS), "pathPattern") => data.path_pattern = value,
+            (Some(ANDROID_NS), "pathPrefix") => data.path_prefix = value,
+            (Some(ANDROID_NS), "mimeType") => data.mime_type = value,
+            _ => unsupported_attribute(node, &attr)?,
+        }
+    }
+
+    Ok(data)
+}
+
+fn required_name(node: Node) -> eyre::Result<String> {
+    node.attribute((ANDROID_NS, "name"))
+        .map(String::from)
+        .ok_or_else(|| eyre::eyre!("`<{}>` requires `android:name`", node.tag_name().name()))
+}
+
+fn parse<T>(attr: &Attribute) -> eyre::Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    attr.value().parse().map_err(|err| {
+        eyre::eyre!(
+            "Invalid value `{}` for `{}`: {}",
+            attr.value(),
+            attr.name(),
+            err
+        )
+    })
+}
+
+fn unsupported_element(node: Node) -> eyre::Result<()> {
+    eyre::bail!(
+        "Element `<{}>` is not supported in the manifest overlay",
+        node.tag_name().name()
+    )
+}
+
+fn unsupported_attribute(node: Node, attr: &Attribute) -> eyre::Result<()> {
+    eyre::bail!(
+        "Attribute `{}` on `<{}>` is not supported in the manifest overlay",
+        attr.name(),
+        node.tag_name().name()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest() -> apk::AndroidManifest {
+        let mut manifest = apk::AndroidManifest::default();
+
+        manifest.package = Some(String::from("com.example.app"));
+        manifest.application.label = Some(String::from("App"));
+        manifest.application.activities.push(Activity {
+            name: Some(String::from("ori.oriactivity.OriActivity")),
+            launch_mode: Some(String::from("singleTop")),
+            ..Default::default()
+        });
+
+        manifest
+    }
+
+    #[test]
+    fn attributes_win_over_generated_ones() {
+        let mut manifest = manifest();
+        let xml = r#"<manifest xmlns:android="http://schemas.android.com/apk/res/android"
+            package="org.example.app" android:versionCode="7">
+            <uses-sdk android:minSdkVersion="26" />
+            <application android:label="Overlay" />
+        </manifest>"#;
+
+        merge(&mut manifest, xml).unwrap();
+
+        assert_eq!(manifest.package.as_deref(), Some("org.example.app"));
+        assert_eq!(manifest.version_code, Some(7));
+        assert_eq!(manifest.sdk.min_sdk_version, Some(26));
+        assert_eq!(manifest.application.label.as_deref(), Some("Overlay"));
+    }
+
+    #[test]
+    fn elements_are_added() {
+        let mut manifest = manifest();
+        let xml = r#"<manifest xmlns:android="http://schemas.android.com/apk/res/android">
+            <uses-feature android:name="android.hardware.camera" android:required="false" />
+            <uses-feature android:glEsVersion="0x00030001" />
+            <uses-permission android:name="android.permission.CAMERA" />
+            <application>
+                <meta-data android:name="key" android:value="value" />
+            </application>
+        </manifest>"#;
+
+        merge(&mut manifest, xml).unwrap();
+
+        assert_eq!(
+            manifest.uses_feature[0].name.as_deref(),
+            Some("android.hardware.camera")
+        );
+        assert_eq!(manifest.uses_feature[0].required, Some(false));
+        assert_eq!(manifest.uses_feature[1].opengles_version, Some((3, 1)));
+        assert_eq!(
+            manifest.uses_permission[0].name,
+            "android.permission.CAMERA"
+        );
+        assert_eq!(manifest.application.meta_data[0].name, "key");
+    }
+
+    #[test]
+    fn activities_with_the_same_name_are_merged() {
+        let mut manifest = manifest();
+        let xml = r#"<manifest xmlns:android="http://schemas.android.com/apk/res/android">
+            <application>
+                <activity android:name="ori.oriactivity.OriActivity" android:screenOrientation="portrait" />
+                <activity android:name="com.example.Settings" android:exported="false" />
+            </application>
+        </manifest>"#;
+
+        merge(&mut manifest, xml).unwrap();
+
+        let activities = &manifest.application.activities;
+        assert_eq!(activities.len(), 2);
+        assert_eq!(activities[0].launch_mode.as_deref(), Some("singleTop"));
+        assert_eq!(activities[0].orientation.as_deref(), Some("portrait"));
+        assert_eq!(activities[1].name.as_deref(), Some("com.example.Settings"));
+        assert_eq!(activities[1].exported, Some(false));
+    }
+
+    #[test]
+    fn unsupported_overlays_are_rejected() {
+        let overlays = [
+            r#"<application />"#,
+            r#"<manifest><service /></manifest>"#,
+            r#"<manifest xmlns:android="http://schemas.android.com/apk/res/android">
+                <application android:allowBackup="false" />
+            </manifest>"#,
+            r#"<manifest xmlns:android="http://schemas.android.com/apk/res/android">
+                <uses-permission />
+            </manifest>"#,
+            r#"<manifest xmlns:android="http://schemas.android.com/apk/res/android"
+                android:versionCode="seven" />"#,
+        ];
+
+        for xml in overlays {
+            let mut manifest = manifest();
+            assert!(merge(&mut manifest, xml).is_err(), "{}", xml);
+        }
+    }
+}
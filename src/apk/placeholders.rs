@@ -0,0 +1,168 @@
+//! Substitution of `${key}` placeholders in strings.
+
+use apk::manifest::{Activity, IntentFilter, MetaData};
+
+/// Replace every `${key}` in `value` with the result of `lookup(key)`.
+pub fn substitute(value: &str, lookup: impl Fn(&str) -> Option<String>) -> eyre::Result<String> {
+    let mut result = String::new();
+    let mut rest = value;
+
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => eyre::bail!("Unterminated placeholder in `{}`", value),
+        };
+
+        let key = &rest[start + 2..end];
+
+        match lookup(key) {
+            Some(value) => result.push_str(&value),
+            None => eyre::bail!("Unresolved placeholder `${{{}}}`", key),
+        }
+
+        rest = &rest[end + 1..];
+    }
+
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+type Lookup<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+/// Substitute placeholders in every string valued field of `manifest`.
+pub fn substitute_manifest(
+    manifest: &mut apk::AndroidManifest,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> eyre::Result<()> {
+    let lookup: Lookup = &lookup;
+
+    replace_option(&mut manifest.package, lookup)?;
+    replace_option(&mut manifest.version_name, lookup)?;
+
+    for feature in manifest.uses_feature.iter_mut() {
+        replace_option(&mut feature.name, lookup)?;
+    }
+
+    for permission in manifest.uses_permission.iter_mut() {
+        replace(&mut permission.name, lookup)?;
+    }
+
+    let application = &mut manifest.application;
+    replace_option(&mut application.theme, lookup)?;
+    replace_option(&mut application.icon, lookup)?;
+    replace_option(&mut application.label, lookup)?;
+    replace_option(&mut application.app_component_factory, lookup)?;
+    replace_meta_data(&mut application.meta_data, lookup)?;
+
+    for activity in application.activities.iter_mut() {
+        replace_activity(activity, lookup)?;
+    }
+
+    Ok(())
+}
+
+fn replace_activity(activity: &mut Activity, lookup: Lookup) -> eyre::Result<()> {
+    replace_option(&mut activity.config_changes, lookup)?;
+    replace_option(&mut activity.label, lookup)?;
+    replace_option(&mut activity.launch_mode, lookup)?;
+    replace_option(&mut activity.name, lookup)?;
+    replace_option(&mut activity.orientation, lookup)?;
+    replace_option(&mut activity.window_soft_input_mode, lookup)?;
+    replace_meta_data(&mut activity.meta_data, lookup)?;
+
+    for filter in activity.intent_filters.iter_mut() {
+        replace_intent_filter(filter, lookup)?;
+    }
+
+    Ok(())
+}
+
+fn replace_intent_filter(filter: &mut IntentFilter, lookup: Lookup) -> eyre::Result<()> {
+    for action in filter.actions.iter_mut() {
+        replace(action, lookup)?;
+    }
+
+    for category in filter.categories.iter_mut() {
+        replace(category, lookup)?;
+    }
+
+    for data in filter.data.iter_mut() {
+        replace_option(&mut data.scheme, lookup)?;
+        replace_option(&mut data.host, lookup)?;
+        replace_option(&mut data.port, lookup)?;
+        replace_option(&mut data.path, lookup)?;
+        replace_option(&mut data.path_pattern, lookup)?;
+        replace_option(&mut data.path_prefix, lookup)?;
+        replace_option(&mut data.mime_type, lookup)?;
+    }
+
+    Ok(())
+}
+
+fn replace_meta_data(meta_data: &mut [MetaData], lookup: Lookup) -> eyre::Result<()> {
+    for entry in meta_data.iter_mut() {
+        replace(&mut entry.name, lookup)?;
+        replace(&mut entry.value, lookup)?;
+    }
+
+    Ok(())
+}
+
+fn replace(value: &mut String, lookup: Lookup) -> eyre::Result<()> {
+    *value = substitute(value, lookup)?;
+    Ok(())
+}
+
+fn replace_option(value: &mut Option<String>, lookup: Lookup) -> eyre::Result<()> {
+    match value {
+        Some(value) => replace(value, lookup),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(key: &str) -> Option<String> {
+        match key {
+            "org" => Some(String::from("acme")),
+            "empty" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn substitutes_placeholders() {
+        assert_eq!(
+            substitute("com.${org}.app", lookup).unwrap(),
+            "com.acme.app"
+        );
+        assert_eq!(substitute("${org}${org}", lookup).unwrap(), "acmeacme");
+        assert_eq!(substitute("a${empty}b", lookup).unwrap(), "ab");
+    }
+
+    #[test]
+    fn keeps_values_without_placeholders() {
+        assert_eq!(
+            substitute("com.example.app", lookup).unwrap(),
+            "com.example.app"
+        );
+        assert_eq!(substitute("$org {org}", lookup).unwrap(), "$org {org}");
+    }
+
+    #[test]
+    fn substituted_values_are_not_substituted_again() {
+        let lookup = |_: &str| Some(String::from("${org}"));
+        assert_eq!(substitute("${key}", lookup).unwrap(), "${org}");
+    }
+
+    #[test]
+    fn rejects_unresolved_placeholders() {
+        assert!(substitute("${missing}", lookup).is_err());
+        assert!(substitute("${org", lookup).is_err());
+    }
+}
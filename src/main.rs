@@ -1,21 +1,79 @@
+/// Print a human readable status line, to stderr when JSON messages are
+/// written to stdout, without colors unless they are enabled.
+macro_rules! status {
+    ($($arg:tt)*) => {{
+        let line = $crate::strip_colors(format!($($arg)*));
+
+        if $crate::is_json_output() {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+    }};
+}
+
 mod apk;
+mod timings;
 
-use std::{io, process};
+use std::{
+    env,
+    io::{self, IsTerminal},
+    path::Path,
+    process,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use eyre::Context;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::EnvFilter;
 
-fn main() -> eyre::Result<()> {
-    color_eyre::install()?;
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+static COLOR: AtomicBool = AtomicBool::new(true);
 
+fn main() -> eyre::Result<()> {
     let Options::Ori(options) = Options::parse();
 
-    run_command(options.command)?;
+    if let MessageFormat::Json = options.global.message_format {
+        JSON_OUTPUT.store(true, Ordering::Relaxed);
+    }
+
+    let color = options.global.color.enabled();
+    COLOR.store(color, Ordering::Relaxed);
+
+    let theme = if color {
+        color_eyre::config::Theme::dark()
+    } else {
+        color_eyre::config::Theme::new()
+    };
+
+    color_eyre::config::HookBuilder::new()
+        .theme(theme)
+        .install()?;
+
+    init_tracing(options.command.verbosity());
+
+    run_command(options.command, &options.global)?;
 
     Ok(())
 }
 
+/// Log diagnostics to stderr, filtered by `RUST_LOG` or else the verbosity.
+fn init_tracing(verbosity: u8) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| match verbosity {
+        0 => EnvFilter::new("warn"),
+        1 => EnvFilter::new("cargo_ori=debug"),
+        _ => EnvFilter::new("cargo_ori=trace"),
+    });
+
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr)
+        .with_ansi(is_color())
+        .without_time()
+        .init();
+}
+
 #[derive(Parser)]
 enum Options {
     /// Ori is a tool for building ori projects.
@@ -24,11 +82,158 @@ enum Options {
 
 #[derive(Parser)]
 struct Ori {
+    #[clap(flatten)]
+    global: GlobalOptions,
+
     /// The subcommand to run.
     #[clap(subcommand)]
     command: Command,
 }
 
+#[derive(Parser)]
+pub struct GlobalOptions {
+    /// Answer yes to all prompts.
+    #[clap(short, long, global = true)]
+    pub yes: bool,
+
+    /// Never prompt, fail instead when an answer is required.
+    #[clap(long, global = true)]
+    pub non_interactive: bool,
+
+    /// Print what would be done without building, installing or removing
+    /// anything.
+    #[clap(long, global = true)]
+    pub dry_run: bool,
+
+    /// The output format for messages.
+    #[clap(long, global = true, value_enum, default_value_t)]
+    pub message_format: MessageFormat,
+
+    /// When to use colors, also passed on to cargo.
+    #[clap(long, global = true, value_enum, default_value_t)]
+    pub color: ColorChoice,
+
+    /// The serial of the device to use, as listed by `apk devices`, when
+    /// several are connected.
+    #[clap(long, global = true, value_name = "SERIAL")]
+    pub device: Option<String>,
+}
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum ColorChoice {
+    /// Colors when writing to a terminal, unless `NO_COLOR` is set or
+    /// `CLICOLOR_FORCE` forces them.
+    #[default]
+    Auto,
+
+    /// Always use colors.
+    Always,
+
+    /// Never use colors.
+    Never,
+}
+
+impl ColorChoice {
+    fn enabled(self) -> bool {
+        let is_set = |var| env::var(var).is_ok_and(|value| !value.is_empty() && value != "0");
+
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto if env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) => {
+                false
+            }
+            ColorChoice::Auto if is_set("CLICOLOR_FORCE") => true,
+            ColorChoice::Auto if is_json_output() => io::stderr().is_terminal(),
+            ColorChoice::Auto => io::stdout().is_terminal(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum MessageFormat {
+    /// Human readable output.
+    #[default]
+    Human,
+
+    /// JSON messages on stdout, human readable output on stderr.
+    Json,
+}
+
+/// A machine readable message, emitted with `--message-format json`.
+#[derive(Serialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+pub enum Message<'a> {
+    BuildStarted { package: &'a str, target: &'a str },
+    ApkSigned { fingerprint: &'a str },
+    BuildFinished { apk: &'a Path },
+    InstallFinished { device: &'a str, success: bool },
+}
+
+pub fn is_json_output() -> bool {
+    JSON_OUTPUT.load(Ordering::Relaxed)
+}
+
+pub fn is_color() -> bool {
+    COLOR.load(Ordering::Relaxed)
+}
+
+/// The `--color` to pass to cargo, whose output is captured and can't detect
+/// the terminal itself.
+pub fn cargo_color() -> &'static str {
+    if is_color() {
+        "always"
+    } else {
+        "never"
+    }
+}
+
+/// Remove the ANSI escape sequences of `line` when colors are disabled.
+pub fn strip_colors(line: String) -> String {
+    if is_color() {
+        return line;
+    }
+
+    let mut stripped = String::with_capacity(line.len());
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            stripped.push(c);
+            continue;
+        }
+
+        // a CSI sequence ends with its first byte in `@..=~`
+        if chars.next() == Some('[') {
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+        }
+    }
+
+    stripped
+}
+
+pub fn emit(message: &Message) {
+    if is_json_output() {
+        match serde_json::to_string(message) {
+            Ok(json) => println!("{}", json),
+            Err(err) => eprintln!("Failed to serialize message: {}", err),
+        }
+    }
+}
+
+impl GlobalOptions {
+    /// Whether prompts can be shown, `CI` being set or stdin not being a
+    /// terminal implies `--non-interactive`.
+    pub fn is_interactive(&self) -> bool {
+        let ci = env::var("CI").is_ok_and(|ci| !ci.is_empty() && ci != "false");
+        !self.non_interactive && !ci && io::stdin().is_terminal()
+    }
+}
+
 #[derive(Subcommand)]
 enum Command {
     /// APK is a tool for working with Android APKs.
@@ -36,9 +241,17 @@ enum Command {
     Apk(apk::Command),
 }
 
-fn run_command(command: Command) -> eyre::Result<()> {
+impl Command {
+    fn verbosity(&self) -> u8 {
+        match self {
+            Command::Apk(command) => command.verbosity(),
+        }
+    }
+}
+
+fn run_command(command: Command, global: &GlobalOptions) -> eyre::Result<()> {
     match command {
-        Command::Apk(command) => command.run(),
+        Command::Apk(command) => command.run(global),
     }
 }
 
@@ -46,7 +259,10 @@ fn run_command(command: Command) -> eyre::Result<()> {
 #[serde(deny_unknown_fields)]
 #[serde(rename_all = "kebab-case")]
 pub struct OriMetadata {
+    /// The application name, used as the label.
     pub name: Option<String>,
+
+    /// The application icon, `[package.metadata.apk]` takes precedence.
     pub icon: Option<String>,
 }
 
@@ -69,23 +285,22 @@ pub fn is_cross_installed() -> bool {
     }
 }
 
-pub fn ensure_cross_installed() -> eyre::Result<()> {
+pub fn ensure_cross_installed(global: &GlobalOptions) -> eyre::Result<()> {
     if is_cross_installed() {
         return Ok(());
     }
 
-    println!("`cross` is not install, do you want to install it? [Y/n] ");
-
-    let mut answer = String::new();
-    io::stdin().read_line(&mut answer)?;
-
-    if answer.trim() == "n" || answer.trim() == "no" {
+    if !confirm(
+        global,
+        "`cross` is not installed, do you want to install it? [Y/n] ",
+        "Installing `cross`",
+    )? {
         eyre::bail!("`cross` is not installed");
     }
 
     let output = process::Command::new("cargo")
         .arg("--color")
-        .arg("always")
+        .arg(cargo_color())
         .arg("install")
         .arg("cross")
         .arg("--git")
@@ -99,19 +314,98 @@ pub fn ensure_cross_installed() -> eyre::Result<()> {
     Ok(())
 }
 
-pub fn get_cargo_metadata() -> eyre::Result<cargo_metadata::Metadata> {
-    let mut args = std::env::args().skip_while(|v| !v.starts_with("--manifest-path"));
+pub fn ensure_rust_target_installed(global: &GlobalOptions, target: &str) -> eyre::Result<()> {
+    let output = process::Command::new("rustup")
+        .arg("target")
+        .arg("list")
+        .arg("--installed")
+        .output();
 
-    let mut cmd = cargo_metadata::MetadataCommand::new();
-    match args.next() {
-        Some(ref p) if p == "--manifest-path" => {
-            cmd.manifest_path(args.next().unwrap());
-        }
-        Some(p) => {
-            cmd.manifest_path(p.trim_start_matches("--manifest-path="));
-        }
-        None => {}
+    // without rustup the installed targets are unknown, leave it to cargo
+    let installed = match output {
+        Ok(output) if output.status.success() => output.stdout,
+        _ => return Ok(()),
     };
 
+    if String::from_utf8_lossy(&installed)
+        .lines()
+        .any(|line| line.trim() == target)
+    {
+        return Ok(());
+    }
+
+    let question = format!(
+        "Target `{}` is not installed, do you want to install it? [Y/n] ",
+        target
+    );
+
+    let action = format!("Installing target `{}`", target);
+
+    if !confirm(global, &question, &action)? {
+        eyre::bail!("Target `{}` is not installed", target);
+    }
+
+    let output = process::Command::new("rustup")
+        .arg("target")
+        .arg("add")
+        .arg(target)
+        .output()?;
+
+    if !output.status.success() {
+        eyre::bail!("Target `{}` could not be installed", target);
+    }
+
+    Ok(())
+}
+
+/// Ask `question`, or with `--yes` assume yes. When prompts can't be shown
+/// the error reports that `action` needs confirmation.
+pub fn confirm(global: &GlobalOptions, question: &str, action: &str) -> eyre::Result<bool> {
+    if global.yes {
+        return Ok(true);
+    }
+
+    if !global.is_interactive() {
+        eyre::bail!("{} requires confirmation, use `--yes` to confirm", action);
+    }
+
+    status!("{}", question);
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    Ok(answer.trim() != "n" && answer.trim() != "no")
+}
+
+pub fn get_cargo_metadata(
+    manifest_path: Option<&Path>,
+    target_dir: Option<&Path>,
+    other_options: Vec<String>,
+) -> eyre::Result<cargo_metadata::Metadata> {
+    let mut cmd = cargo_metadata::MetadataCommand::new();
+    cmd.other_options(other_options);
+
+    if let Some(manifest_path) = manifest_path {
+        cmd.manifest_path(manifest_path);
+    } else {
+        let mut args = std::env::args().skip_while(|v| !v.starts_with("--manifest-path"));
+
+        match args.next() {
+            Some(ref p) if p == "--manifest-path" => {
+                if let Some(p) = args.next() {
+                    cmd.manifest_path(p);
+                }
+            }
+            Some(p) => {
+                cmd.manifest_path(p.trim_start_matches("--manifest-path="));
+            }
+            None => {}
+        };
+    }
+
+    if let Some(target_dir) = target_dir {
+        cmd.env("CARGO_TARGET_DIR", target_dir);
+    }
+
     cmd.exec().wrap_err("Failed to get cargo metadata")
 }
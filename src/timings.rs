@@ -0,0 +1,34 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use owo_colors::OwoColorize;
+
+static TIMINGS: Mutex<Vec<(String, Duration)>> = Mutex::new(Vec::new());
+
+/// Record the time spent in `phase` since `start`.
+pub fn record(phase: impl Into<String>, start: Instant) {
+    let mut timings = TIMINGS.lock().unwrap_or_else(|err| err.into_inner());
+    timings.push((phase.into(), start.elapsed()));
+}
+
+/// Print the recorded timings as a table and clear them.
+pub fn print() {
+    let timings = std::mem::take(&mut *TIMINGS.lock().unwrap_or_else(|err| err.into_inner()));
+
+    let width = timings
+        .iter()
+        .map(|(phase, _)| phase.len())
+        .max()
+        .unwrap_or(0);
+    let total: Duration = timings.iter().map(|(_, duration)| *duration).sum();
+
+    status!("     {}", "Timings".green().bold());
+
+    for (phase, duration) in timings.iter() {
+        status!("       {:width$}  {:>8.2}s", phase, duration.as_secs_f64());
+    }
+
+    status!("       {:width$}  {:>8.2}s", "total", total.as_secs_f64());
+}
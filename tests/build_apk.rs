@@ -0,0 +1,96 @@
+//! Builds the cdylib fixture with the `cargo-ori` binary.
+
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+const TARGET: &str = "x86_64-linux-android";
+
+fn fixture_manifest() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/cdylib/Cargo.toml")
+}
+
+/// Run `cargo ori apk build` on the fixture with `args` before the command.
+fn build(args: &[&str], target_dir: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_cargo-ori"))
+        .arg("ori")
+        .arg("--yes")
+        .arg("--color")
+        .arg("never")
+        .args(args)
+        .arg("apk")
+        .arg("build")
+        .arg("--no-cross")
+        .arg("--target")
+        .arg(TARGET)
+        .arg("--manifest-path")
+        .arg(fixture_manifest())
+        .arg("--target-dir")
+        .arg(target_dir)
+        .output()
+        .expect("cargo-ori runs")
+}
+
+#[test]
+fn dry_run_plans_the_apk() {
+    let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("dry-run");
+    let output = build(&["--dry-run"], &target_dir);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "{}{}",
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // single target APKs are written next to the library
+    let apk_path = target_dir
+        .join(TARGET)
+        .join("debug")
+        .join("cdylib-fixture.apk");
+    assert!(stdout.contains(&format!("Plan create APK `{}`", apk_path.display())));
+    assert!(stdout.contains("manifest for `com.example.cdylib_fixture.debug`"));
+    assert!(stdout.contains("sign with the debug key"));
+    assert!(!apk_path.exists());
+}
+
+#[test]
+#[ignore = "needs network access, the `x86_64-linux-android` target and \
+            `CARGO_TARGET_X86_64_LINUX_ANDROID_LINKER` set to an NDK linker"]
+fn builds_a_valid_apk() {
+    let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("build");
+    let output = build(&["--message-format", "json"], &target_dir);
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // stdout has only JSON messages
+    let messages: Vec<serde_json::Value> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("stdout is JSON"))
+        .collect();
+
+    let apk_path = messages
+        .iter()
+        .find(|message| message["reason"] == "build-finished")
+        .and_then(|message| message["apk"].as_str())
+        .expect("a build-finished message");
+
+    let mut apk = zip::ZipArchive::new(File::open(apk_path).unwrap()).expect("the APK is a zip");
+
+    for name in ["AndroidManifest.xml", "resources.arsc", "classes.dex"] {
+        assert!(apk.by_name(name).is_ok(), "`{}` in the APK", name);
+    }
+
+    // native libraries are stored by default
+    let lib = apk
+        .by_name("lib/x86_64/libcdylib_fixture.so")
+        .expect("the library in the APK");
+    assert_eq!(lib.compression(), zip::CompressionMethod::Stored);
+}
@@ -0,0 +1,7 @@
+//! Only compiles with both the configured and the extra rustflags.
+
+#[cfg(not(configured_rustflags))]
+compile_error!("the configured `target.'cfg(all())'.rustflags` were dropped");
+
+#[cfg(not(extra_rustflags))]
+compile_error!("the extra rustflags were dropped");